@@ -0,0 +1,160 @@
+use crate::client::ClientAccount;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt};
+
+/// An account of the double-entry books beneath the client accounts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BookAccount {
+    /// Funds of the client available for withdrawal.
+    ClientAvailable(u16),
+    /// Funds of the client held by disputes.
+    ClientHeld(u16),
+    /// Funds deposited from or withdrawn to outside the system.
+    ExternalFunding,
+    /// Withdrawn funds provisionally credited back while the withdrawal is disputed.
+    DisputedWithdrawals,
+    /// Funds returned by chargebacks.
+    ChargebackLoss,
+}
+
+impl fmt::Display for BookAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ClientAvailable(client) => write!(f, "client {client} available"),
+            Self::ClientHeld(client) => write!(f, "client {client} held"),
+            Self::ExternalFunding => write!(f, "external funding"),
+            Self::DisputedWithdrawals => write!(f, "disputed withdrawals"),
+            Self::ChargebackLoss => write!(f, "chargeback loss"),
+        }
+    }
+}
+
+/// A signed amount posted to an account of the books. The entries posted by an
+/// operation sum to zero; client accounts carry the funds of the client as
+/// positive amounts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub account: BookAccount,
+    pub amount: Decimal,
+}
+
+impl Entry {
+    /// Constructs a new [`Entry`].
+    pub fn new(account: BookAccount, amount: Decimal) -> Self {
+        Self { account, amount }
+    }
+}
+
+/// The balances of all accounts of the books.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrialBalance {
+    /// The balance of every account of the books.
+    pub balances: BTreeMap<BookAccount, Decimal>,
+    /// Clients whose stored balances differ from their accounts of the books.
+    pub mismatched_clients: Vec<u16>,
+}
+
+impl TrialBalance {
+    /// Constructs a new, empty [`TrialBalance`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the entries posted by the account, checking them against its balances.
+    pub fn add_account(&mut self, account: &ClientAccount) {
+        let (mut available, mut held) = (Decimal::ZERO, Decimal::ZERO);
+
+        for entry in account.events().iter().flat_map(|event| &event.entries) {
+            *self.balances.entry(entry.account).or_default() += entry.amount;
+            match entry.account {
+                BookAccount::ClientAvailable(_) => available += entry.amount,
+                BookAccount::ClientHeld(_) => held += entry.amount,
+                _ => {}
+            }
+        }
+
+        if available != account.available()
+            || held != account.held()
+            || available + held != account.total()
+        {
+            self.mismatched_clients.push(account.client());
+        }
+    }
+
+    /// The sum of all balances, zero if the books balance.
+    pub fn sum(&self) -> Decimal {
+        self.balances.values().sum()
+    }
+
+    /// True if the books sum to zero and agree with the balances of every client.
+    pub fn is_balanced(&self) -> bool {
+        self.sum().is_zero() && self.mismatched_clients.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::{ClientConfig, WithdrawalDisputePolicy},
+        types::TransactionType,
+    };
+
+    #[test]
+    fn test_trial_balance() {
+        let config = ClientConfig {
+            withdrawal_disputes: WithdrawalDisputePolicy::Support,
+            ..Default::default()
+        };
+        let mut account = ClientAccount::with_config(1, config);
+        for tx in [
+            TransactionType::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Decimal::new(50, 1),
+            },
+            TransactionType::Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: Decimal::new(20, 1),
+            },
+            TransactionType::Dispute { client: 1, tx: 2 },
+            TransactionType::Chargeback { client: 1, tx: 2 },
+        ] {
+            account.process_transaction(tx).unwrap();
+        }
+
+        for event in account.events() {
+            let sum: Decimal = event.entries.iter().map(|entry| entry.amount).sum();
+            assert!(sum.is_zero(), "unbalanced {event:?}");
+        }
+
+        let mut trial = TrialBalance::new();
+        trial.add_account(&account);
+        assert!(trial.is_balanced());
+        assert_eq!(
+            trial.balances[&BookAccount::ClientAvailable(1)],
+            Decimal::new(50, 1)
+        );
+        assert_eq!(
+            trial.balances[&BookAccount::ExternalFunding],
+            Decimal::new(-30, 1)
+        );
+        assert_eq!(
+            trial.balances[&BookAccount::ChargebackLoss],
+            Decimal::new(-20, 1)
+        );
+        assert!(trial.balances[&BookAccount::DisputedWithdrawals].is_zero());
+
+        // Balances changed outside of the books.
+        account.available += Decimal::ONE;
+        let mut trial = TrialBalance::new();
+        trial.add_account(&account);
+        assert!(trial.sum().is_zero());
+        assert_eq!(trial.mismatched_clients, [1]);
+        assert!(!trial.is_balanced());
+    }
+}
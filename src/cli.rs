@@ -0,0 +1,361 @@
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+use tx::{
+    client::{RedisputePolicy, WithdrawalDisputePolicy},
+    input::InputFormat,
+    invariant::InvariantCheck,
+    rejection::RejectionFormat,
+    server::Protocol,
+    AccountOrder, AmountFormat, ClientConfig, OutputFormat, OutputOptions, Rounding,
+};
+
+/// Transaction engine processing deposits, withdrawals and disputes.
+///
+/// Without a subcommand the inputs are processed as with `tx process`.
+#[derive(Debug, Parser)]
+#[command(version, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub process: ProcessArgs,
+}
+
+impl Cli {
+    /// The subcommand to run, defaulting to `process`.
+    pub fn command(self) -> Command {
+        self.command.unwrap_or(Command::Process(self.process))
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Process the transactions and write the state of all accounts.
+    Process(ProcessArgs),
+    /// Parse the transactions without processing them and report malformed rows.
+    Validate(ValidateArgs),
+    /// Process the transactions and write the outcome of every row.
+    Replay(ReplayArgs),
+    /// Process the transactions and show the state of a single client.
+    Inspect(InspectArgs),
+    /// Process the transactions and write the ledger of a single client with its balances.
+    Statement(StatementArgs),
+    /// Process the transactions and check that the books beneath the accounts balance.
+    TrialBalance(TrialBalanceArgs),
+    /// Check all invariants of the accounts of a snapshot.
+    Audit(AuditArgs),
+    /// Serve transactions and account queries over a TCP or Unix socket.
+    Serve(ServeArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct InputArgs {
+    /// Input files, processed in order. `-` reads from stdin.
+    #[arg(required = true)]
+    pub inputs: Vec<String>,
+
+    /// Format of the inputs (csv, jsonl). Derived from the extension if not set.
+    #[arg(long)]
+    pub input_format: Option<InputFormat>,
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// Abort at the first malformed or rejected row instead of skipping it.
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Write the run summary as JSON to the given path instead of printing it to stderr.
+    #[arg(long)]
+    pub summary: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ConfigArgs {
+    /// Handling of disputes referencing a withdrawal (reject, support).
+    #[arg(long, default_value = "reject")]
+    pub withdrawal_disputes: WithdrawalDisputePolicy,
+
+    /// Whether resolved transactions may be disputed again (deny, allow).
+    #[arg(long, default_value = "deny")]
+    pub redispute: RedisputePolicy,
+
+    /// Check the invariants of the accounts after every transaction (off, report, abort).
+    /// Defaults to report in debug builds and off in release builds.
+    #[arg(long)]
+    pub check_invariants: Option<InvariantCheck>,
+}
+
+impl ConfigArgs {
+    pub fn config(&self) -> ClientConfig {
+        ClientConfig {
+            withdrawal_disputes: self.withdrawal_disputes,
+            redispute: self.redispute,
+            invariants: self.check_invariants.unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct EngineArgs {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+
+    /// Keep the accounts in the given directory, continuing from the state of previous runs.
+    #[arg(long)]
+    pub storage: Option<PathBuf>,
+
+    /// Restore the engine from the given snapshot before processing.
+    #[arg(long)]
+    pub load_snapshot: Option<PathBuf>,
+
+    /// Write a snapshot of the engine to the given path after processing.
+    #[arg(long)]
+    pub save_snapshot: Option<PathBuf>,
+
+    /// Record the rows of the run in the given journal. The state is rebuilt from an
+    /// existing journal, and an unfinished run over the same unchanged inputs
+    /// resumes after its last recorded row. The journal holds the whole state, so it
+    /// cannot be combined with a snapshot to restore.
+    #[arg(long, conflicts_with_all = ["storage", "load_snapshot"])]
+    pub journal: Option<PathBuf>,
+
+    /// Start a new run even if the journal has an unfinished run over other or
+    /// changed inputs.
+    #[arg(long, requires = "journal")]
+    pub journal_restart: bool,
+
+    /// Commit the journal to disk every given number of records.
+    #[arg(long, default_value_t = 1)]
+    pub journal_commit: u64,
+}
+
+impl EngineArgs {
+    pub fn config(&self) -> ClientConfig {
+        self.config.config()
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct RejectionArgs {
+    /// Write the rejected rows to the given path.
+    #[arg(long)]
+    pub rejections: Option<String>,
+
+    /// Format of the rejection report (csv, json, jsonl). Derived from the extension if not set.
+    #[arg(long)]
+    pub rejections_format: Option<RejectionFormat>,
+}
+
+impl RejectionArgs {
+    /// Location and format of the rejection report, if requested.
+    pub fn report(&self) -> Option<(&str, RejectionFormat)> {
+        self.rejections.as_deref().map(|path| {
+            let format = self
+                .rejections_format
+                .unwrap_or_else(|| RejectionFormat::from_path(path));
+            (path, format)
+        })
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct OutputArgs {
+    /// Write the output to the given path instead of stdout.
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+
+    /// Number of decimal places of the amounts, at most 28.
+    #[arg(
+        long,
+        default_value_t = AmountFormat::default().scale,
+        value_parser = clap::value_parser!(u32).range(0..=28)
+    )]
+    pub scale: u32,
+
+    /// Rounding of the amounts (half-even, half-up, down, up).
+    #[arg(long, default_value = "half-even")]
+    pub rounding: Rounding,
+}
+
+impl OutputArgs {
+    pub fn amounts(&self) -> AmountFormat {
+        AmountFormat {
+            scale: self.scale,
+            rounding: self.rounding,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ProcessArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    #[command(flatten)]
+    pub engine: EngineArgs,
+
+    #[command(flatten)]
+    pub rejections: RejectionArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    /// Format of the account output (csv, json, jsonl, table).
+    #[arg(long, default_value = "csv")]
+    pub format: OutputFormat,
+
+    /// Order of the accounts (client, client-desc, total-desc).
+    #[arg(long, default_value = "client")]
+    pub order: AccountOrder,
+
+    /// Process the transactions on the given number of threads, sharding the
+    /// accounts by client ID. With --strict the rows are processed one at a time.
+    #[arg(long, default_value_t = 1, conflicts_with = "storage")]
+    pub threads: usize,
+}
+
+impl ProcessArgs {
+    pub fn options(&self) -> OutputOptions {
+        OutputOptions {
+            order: self.order,
+            amounts: self.output.amounts(),
+            format: self.format,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ValidateArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    #[command(flatten)]
+    pub rejections: RejectionArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ReplayArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    #[command(flatten)]
+    pub engine: EngineArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct InspectArgs {
+    /// The client to inspect.
+    pub client: u16,
+
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    #[command(flatten)]
+    pub engine: EngineArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    /// Format of the output (table, json, jsonl).
+    #[arg(long, default_value = "table", value_parser = inspect_format)]
+    pub format: OutputFormat,
+}
+
+/// Parse the format of `tx inspect`, which has no CSV form.
+fn inspect_format(s: &str) -> Result<OutputFormat, String> {
+    match s.parse()? {
+        OutputFormat::Csv => Err("csv is not supported by inspect".to_string()),
+        format => Ok(format),
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct StatementArgs {
+    /// The client of the statement.
+    pub client: u16,
+
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    #[command(flatten)]
+    pub engine: EngineArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    /// Only include the events up to the given sequence number.
+    #[arg(long)]
+    pub as_of: Option<u64>,
+
+    /// Format of the statement (csv, json, jsonl, table).
+    #[arg(long, default_value = "csv")]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+pub struct TrialBalanceArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    #[command(flatten)]
+    pub engine: EngineArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct AuditArgs {
+    /// The snapshot to audit, written with `--save-snapshot`.
+    pub snapshot: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct ServeArgs {
+    /// Address to listen on, `<host>:<port>` for TCP or `unix:<path>` for a Unix socket.
+    pub listen: String,
+
+    /// Protocol of the connections (lines, http).
+    #[arg(long, default_value = "lines")]
+    pub protocol: Protocol,
+
+    #[command(flatten)]
+    pub config: ConfigArgs,
+
+    /// Restore the engine from the given snapshot before serving.
+    #[arg(long)]
+    pub load_snapshot: Option<PathBuf>,
+
+    /// Append the accepted transactions to the given journal. The state is rebuilt
+    /// from an existing journal before serving, so it cannot be combined with a
+    /// snapshot to restore.
+    #[arg(long, conflicts_with = "load_snapshot")]
+    pub journal: Option<PathBuf>,
+
+    /// Commit the journal to disk every given number of records.
+    #[arg(long, default_value_t = 1)]
+    pub journal_commit: u64,
+}
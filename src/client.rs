@@ -1,17 +1,155 @@
-use crate::types::{TransactionType, PRECISION};
+use crate::{
+    books::{BookAccount, Entry},
+    invariant::{self, InvariantCheck, Violation},
+    ledger::{Balances, EventKind, LedgerEvent},
+    types::{TransactionType, PRECISION},
+};
 
 use rust_decimal::Decimal;
-use std::collections::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
+/// Reasons a [`ClientAccount`] rejects a transaction.
 #[derive(Debug)]
 pub enum ClientErr {
+    /// The account is locked.
     AccountLocked,
+    /// The available funds do not cover the amount, or the amount is negative.
     InsufficientFunds,
+    /// The referenced transaction was not processed by this account.
     DisputedTransactionNotFound,
+    /// The transaction ID was already processed.
     AlreadyProcessed,
+    /// Disputes of withdrawals are rejected by the [`WithdrawalDisputePolicy`].
+    WithdrawalDisputeNotSupported,
+    /// The referenced transaction is not in a state allowing the operation.
+    InvalidDisputeState(DisputeState),
+    /// The transaction would violate an invariant checked with [`InvariantCheck::Abort`].
+    InvariantViolated(Violation),
 }
 
-#[derive(Debug)]
+impl ClientErr {
+    /// A stable, machine-readable code of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::AccountLocked => "account_locked",
+            Self::InsufficientFunds => "insufficient_funds",
+            Self::DisputedTransactionNotFound => "disputed_transaction_not_found",
+            Self::AlreadyProcessed => "already_processed",
+            Self::WithdrawalDisputeNotSupported => "withdrawal_dispute_not_supported",
+            Self::InvalidDisputeState(_) => "invalid_dispute_state",
+            Self::InvariantViolated(_) => "invariant_violated",
+        }
+    }
+}
+
+impl std::fmt::Display for ClientErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::AccountLocked => write!(f, "account is locked"),
+            Self::InsufficientFunds => write!(f, "insufficient funds"),
+            Self::DisputedTransactionNotFound => write!(f, "disputed transaction not found"),
+            Self::AlreadyProcessed => write!(f, "transaction already processed"),
+            Self::WithdrawalDisputeNotSupported => {
+                write!(f, "disputes of withdrawals are not supported")
+            }
+            Self::InvalidDisputeState(state) => {
+                write!(f, "invalid dispute state of transaction: {state:?}")
+            }
+            Self::InvariantViolated(violation) => write!(f, "invariant violated: {violation}"),
+        }
+    }
+}
+
+impl std::error::Error for ClientErr {}
+
+/// Dispute lifecycle of a processed transaction.
+///
+/// ```text
+/// Processed -> Disputed -> Resolved
+///                       -> ChargedBack
+/// ```
+///
+/// A resolved transaction may be disputed again if allowed by the
+/// [`RedisputePolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DisputeState {
+    /// The transaction was processed and is not disputed.
+    Processed,
+    /// The transaction is under dispute.
+    Disputed,
+    /// The dispute was resolved.
+    Resolved,
+    /// The dispute ended with a chargeback.
+    ChargedBack,
+}
+
+/// Whether resolved transactions may be disputed again.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RedisputePolicy {
+    /// A resolved transaction cannot be disputed again.
+    #[default]
+    Deny,
+    /// A resolved transaction can be disputed again.
+    Allow,
+}
+
+impl std::str::FromStr for RedisputePolicy {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deny" => Ok(Self::Deny),
+            "allow" => Ok(Self::Allow),
+            _ => Err("Unknown redispute policy"),
+        }
+    }
+}
+
+/// How disputes referencing a withdrawal are handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WithdrawalDisputePolicy {
+    /// Disputes of withdrawals are rejected with an error.
+    #[default]
+    Reject,
+    /// Disputes of withdrawals are supported. The withdrawn amount is
+    /// provisionally credited to the held funds while under dispute. A resolve
+    /// confirms the withdrawal, a chargeback returns the funds to the client.
+    Support,
+}
+
+impl std::str::FromStr for WithdrawalDisputePolicy {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Self::Reject),
+            "support" => Ok(Self::Support),
+            _ => Err("Unknown withdrawal dispute policy"),
+        }
+    }
+}
+
+/// Configuration of a [`ClientAccount`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClientConfig {
+    pub withdrawal_disputes: WithdrawalDisputePolicy,
+    pub redispute: RedisputePolicy,
+    pub invariants: InvariantCheck,
+}
+
+/// The change of the balances of an account by a processed transaction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceDelta {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    /// True if the transaction locked the account.
+    pub locked: bool,
+}
+
+/// The state of a single client account.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClientAccount {
     client: u16,
     pub(crate) available: Decimal,
@@ -20,12 +158,23 @@ pub struct ClientAccount {
     pub(crate) locked: bool,
 
     processed_tx: HashMap<u32, TransactionType>,
-    under_dispute: HashSet<u32>,
+    dispute_state: HashMap<u32, DisputeState>,
+    /// Every applied operation, in order.
+    events: Vec<LedgerEvent>,
+
+    /// Not stored with the account, the engine applies its own configuration.
+    #[serde(skip)]
+    pub(crate) config: ClientConfig,
 }
 
 impl ClientAccount {
     /// Constructs a new [`ClientAccount`] with the given client ID.
     pub fn new(client: u16) -> Self {
+        Self::with_config(client, ClientConfig::default())
+    }
+
+    /// Constructs a new [`ClientAccount`] with the given client ID and configuration.
+    pub fn with_config(client: u16, config: ClientConfig) -> Self {
         Self {
             client,
             available: Decimal::new(0, PRECISION),
@@ -33,43 +182,202 @@ impl ClientAccount {
             total: Decimal::new(0, PRECISION),
             locked: false,
             processed_tx: HashMap::new(),
-            under_dispute: HashSet::new(),
+            dispute_state: HashMap::new(),
+            events: Vec::new(),
+            config,
         }
     }
 
+    /// The client ID of the account.
+    pub fn client(&self) -> u16 {
+        self.client
+    }
+
+    /// The funds available for withdrawal.
+    pub fn available(&self) -> Decimal {
+        self.available
+    }
+
+    /// The funds held by disputes.
+    pub fn held(&self) -> Decimal {
+        self.held
+    }
+
+    /// The total funds, available and held.
+    pub fn total(&self) -> Decimal {
+        self.total
+    }
+
     /// True if the account is locked.
     pub fn is_locked(&self) -> bool {
         self.locked
     }
 
-    /// Process the transaction.
-    pub fn process_transaction(&mut self, tx: TransactionType) -> Result<(), ClientErr> {
+    /// The dispute state of the given transaction, if it was processed by this account.
+    pub fn dispute_state(&self, tx: u32) -> Option<DisputeState> {
+        self.dispute_state.get(&tx).copied()
+    }
+
+    /// Iterate over the processed deposits and withdrawals with their dispute state,
+    /// in no particular order. Transactions without a dispute state, only found in
+    /// corrupt snapshots, are skipped.
+    pub fn transactions(&self) -> impl Iterator<Item = (&TransactionType, DisputeState)> {
+        self.processed_tx
+            .iter()
+            .filter_map(|(tx, transaction)| Some((transaction, self.dispute_state(*tx)?)))
+    }
+
+    /// IDs of the processed transactions without a dispute state, in order.
+    pub(crate) fn missing_dispute_states(&self) -> Vec<u32> {
+        let mut txs: Vec<_> = self
+            .processed_tx
+            .keys()
+            .filter(|tx| !self.dispute_state.contains_key(tx))
+            .copied()
+            .collect();
+        txs.sort_unstable();
+        txs
+    }
+
+    /// IDs of the dispute states without a processed transaction, in order.
+    pub(crate) fn orphaned_dispute_states(&self) -> Vec<u32> {
+        let mut txs: Vec<_> = self
+            .dispute_state
+            .keys()
+            .filter(|tx| !self.processed_tx.contains_key(tx))
+            .copied()
+            .collect();
+        txs.sort_unstable();
+        txs
+    }
+
+    /// The current balances of the account.
+    fn balances(&self) -> Balances {
+        Balances {
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
+        }
+    }
+
+    /// The ledger of the account: every applied operation, in order.
+    pub fn events(&self) -> &[LedgerEvent] {
+        &self.events
+    }
+
+    /// The balances after the event with the given sequence number.
+    pub fn balances_at(&self, seq: u64) -> Balances {
+        let mut balances = Balances::default();
+        for event in self.events.iter().take_while(|event| event.seq <= seq) {
+            balances.apply(&event.delta);
+        }
+        balances
+    }
+
+    /// Iterate over the events of the ledger with the balances after each of them.
+    pub fn statement(&self) -> impl Iterator<Item = (&LedgerEvent, Balances)> {
+        self.events
+            .iter()
+            .scan(Balances::default(), |balances, event| {
+                balances.apply(&event.delta);
+                Some((event, *balances))
+            })
+    }
+
+    /// Process the transaction, returning the change of the balances.
+    ///
+    /// With [`InvariantCheck::Abort`] the transaction is applied to a copy of the
+    /// account, which replaces it only if no invariant is violated.
+    pub fn process_transaction(&mut self, tx: TransactionType) -> Result<BalanceDelta, ClientErr> {
+        let before = self.balances();
+        match self.config.invariants {
+            InvariantCheck::Off => self.apply(tx),
+            InvariantCheck::Report => {
+                let delta = self.apply(tx)?;
+                for violation in invariant::check_transaction(&before, self) {
+                    log::error!("Invariant violated: {violation}");
+                }
+                Ok(delta)
+            }
+            InvariantCheck::Abort => {
+                let mut updated = self.clone();
+                let delta = updated.apply(tx)?;
+                if let Some(violation) = invariant::check_transaction(&before, &updated)
+                    .into_iter()
+                    .next()
+                {
+                    return Err(ClientErr::InvariantViolated(violation));
+                }
+                *self = updated;
+                Ok(delta)
+            }
+        }
+    }
+
+    fn apply(&mut self, tx: TransactionType) -> Result<BalanceDelta, ClientErr> {
         if self.is_locked() {
             return Err(ClientErr::AccountLocked);
         }
 
-        match tx {
+        let before = self.balances();
+        let (kind, tx_id) = (EventKind::of(&tx), tx.transaction_id());
+
+        let entries = match tx {
             TransactionType::Deposit {
                 tx: tx_id, amount, ..
             } => {
-                self.handle_deposit(tx_id, amount)?;
+                let entries = self.handle_deposit(tx_id, amount)?;
                 self.processed_tx.insert(tx_id, tx);
+                self.dispute_state.insert(tx_id, DisputeState::Processed);
+                entries
             }
             TransactionType::Withdrawal {
                 tx: tx_id, amount, ..
             } => {
-                self.handle_withdraw(tx_id, amount)?;
+                let entries = self.handle_withdraw(tx_id, amount)?;
                 self.processed_tx.insert(tx_id, tx);
+                self.dispute_state.insert(tx_id, DisputeState::Processed);
+                entries
             }
             TransactionType::Dispute { tx, .. } => self.handle_dispute(tx)?,
             TransactionType::Resolve { tx, .. } => self.handle_resolve(tx)?,
             TransactionType::Chargeback { tx, .. } => self.handle_chargeback(tx)?,
-        }
+        };
+        self.post(&entries);
 
-        Ok(())
+        let delta = BalanceDelta {
+            available: self.available - before.available,
+            held: self.held - before.held,
+            total: self.total - before.total,
+            locked: self.locked,
+        };
+        self.events.push(LedgerEvent {
+            seq: self.events.len() as u64 + 1,
+            kind,
+            tx: tx_id,
+            delta,
+            entries,
+        });
+
+        Ok(delta)
     }
 
-    fn handle_deposit(&mut self, tx: u32, amount: Decimal) -> Result<(), ClientErr> {
+    /// Post balanced entries to the books, updating the balances of the client.
+    fn post(&mut self, entries: &[Entry]) {
+        debug_assert!(entries.iter().map(|e| e.amount).sum::<Decimal>().is_zero());
+
+        for entry in entries {
+            match entry.account {
+                BookAccount::ClientAvailable(_) => self.available += entry.amount,
+                BookAccount::ClientHeld(_) => self.held += entry.amount,
+                _ => {}
+            }
+        }
+        self.total = self.available + self.held;
+    }
+
+    fn handle_deposit(&mut self, tx: u32, amount: Decimal) -> Result<Vec<Entry>, ClientErr> {
         log::debug!("[client {}] handle_deposit {amount} ", self.client);
 
         if self.processed_tx.contains_key(&tx) {
@@ -79,13 +387,13 @@ impl ClientAccount {
             return Err(ClientErr::InsufficientFunds);
         }
 
-        self.available += amount;
-        self.total += amount;
-
-        Ok(())
+        Ok(vec![
+            Entry::new(BookAccount::ClientAvailable(self.client), amount),
+            Entry::new(BookAccount::ExternalFunding, -amount),
+        ])
     }
 
-    fn handle_withdraw(&mut self, tx: u32, amount: Decimal) -> Result<(), ClientErr> {
+    fn handle_withdraw(&mut self, tx: u32, amount: Decimal) -> Result<Vec<Entry>, ClientErr> {
         log::debug!("[client {}] handle_withdraw {amount}", self.client);
         if self.processed_tx.contains_key(&tx) {
             return Err(ClientErr::AlreadyProcessed);
@@ -98,76 +406,133 @@ impl ClientAccount {
             return Err(ClientErr::InsufficientFunds);
         }
 
-        self.available -= amount;
-        self.total -= amount;
-        Ok(())
+        Ok(vec![
+            Entry::new(BookAccount::ClientAvailable(self.client), -amount),
+            Entry::new(BookAccount::ExternalFunding, amount),
+        ])
     }
 
-    fn handle_dispute(&mut self, tx: u32) -> Result<(), ClientErr> {
+    fn handle_dispute(&mut self, tx: u32) -> Result<Vec<Entry>, ClientErr> {
         log::debug!("[client {}] handle_dispute {tx}", self.client);
 
-        if self.under_dispute.contains(&tx) {
-            return Err(ClientErr::AlreadyProcessed);
-        }
-
         let tx = self
             .processed_tx
             .get(&tx)
             .ok_or(ClientErr::DisputedTransactionNotFound)?;
 
+        let state = self
+            .dispute_state(tx.transaction_id())
+            .ok_or(ClientErr::DisputedTransactionNotFound)?;
+        match state {
+            DisputeState::Processed => {}
+            DisputeState::Resolved if self.config.redispute == RedisputePolicy::Allow => {}
+            state => return Err(ClientErr::InvalidDisputeState(state)),
+        }
+
         log::debug!("[client {}] dispute found: {tx:?}", self.client);
 
-        if let TransactionType::Deposit { amount, .. } = tx {
-            self.available -= amount;
-            self.held += amount;
+        let (available, held) = (
+            BookAccount::ClientAvailable(self.client),
+            BookAccount::ClientHeld(self.client),
+        );
+        let entries = match *tx {
+            TransactionType::Deposit { amount, .. } => {
+                vec![Entry::new(available, -amount), Entry::new(held, amount)]
+            }
+            TransactionType::Withdrawal { amount, .. } => {
+                if self.config.withdrawal_disputes == WithdrawalDisputePolicy::Reject {
+                    return Err(ClientErr::WithdrawalDisputeNotSupported);
+                }
+                // Provisionally credit the withdrawn funds as held.
+                vec![
+                    Entry::new(held, amount),
+                    Entry::new(BookAccount::DisputedWithdrawals, -amount),
+                ]
+            }
+            _ => return Ok(Vec::new()),
+        };
+
+        self.dispute_state
+            .insert(tx.transaction_id(), DisputeState::Disputed);
+
+        Ok(entries)
+    }
 
-            self.under_dispute.insert(tx.transaction_id());
+    /// Move a disputed transaction to its final state.
+    fn transition(&mut self, tx: u32, to: DisputeState) -> Result<(), ClientErr> {
+        let state = self
+            .dispute_state
+            .get_mut(&tx)
+            .ok_or(ClientErr::DisputedTransactionNotFound)?;
+
+        if *state != DisputeState::Disputed {
+            return Err(ClientErr::InvalidDisputeState(*state));
         }
 
+        *state = to;
         Ok(())
     }
 
-    fn handle_resolve(&mut self, tx: u32) -> Result<(), ClientErr> {
+    fn handle_resolve(&mut self, tx: u32) -> Result<Vec<Entry>, ClientErr> {
         log::debug!("[client {}] handle_resolve {tx}", self.client);
 
+        // Tx must be marked as disputed to resolve it.
+        self.transition(tx, DisputeState::Resolved)?;
+
         let disputed_tx = self
             .processed_tx
             .get(&tx)
             .ok_or(ClientErr::DisputedTransactionNotFound)?;
 
-        // Tx must be marked as disputed to resolve it.
-        if !self.under_dispute.remove(&disputed_tx.transaction_id()) {
-            return Err(ClientErr::DisputedTransactionNotFound);
-        }
-
-        if let TransactionType::Deposit { amount, .. } = disputed_tx {
-            self.available += amount;
-            self.held -= amount;
-        }
-
-        Ok(())
+        let (available, held) = (
+            BookAccount::ClientAvailable(self.client),
+            BookAccount::ClientHeld(self.client),
+        );
+        Ok(match *disputed_tx {
+            TransactionType::Deposit { amount, .. } => {
+                vec![Entry::new(available, amount), Entry::new(held, -amount)]
+            }
+            // The withdrawal stands, drop the provisional credit.
+            TransactionType::Withdrawal { amount, .. } => vec![
+                Entry::new(held, -amount),
+                Entry::new(BookAccount::DisputedWithdrawals, amount),
+            ],
+            _ => Vec::new(),
+        })
     }
 
-    fn handle_chargeback(&mut self, tx: u32) -> Result<(), ClientErr> {
+    fn handle_chargeback(&mut self, tx: u32) -> Result<Vec<Entry>, ClientErr> {
         log::debug!("[client {}] handle_chargeback {tx}", self.client);
 
+        // Tx must be marked as disputed to chargeback it.
+        self.transition(tx, DisputeState::ChargedBack)?;
+
         let disputed_tx = self
             .processed_tx
             .get(&tx)
             .ok_or(ClientErr::DisputedTransactionNotFound)?;
 
-        // Tx must be marked as disputed to chargeback it.
-        if !self.under_dispute.remove(&disputed_tx.transaction_id()) {
-            return Err(ClientErr::DisputedTransactionNotFound);
-        }
-
-        if let TransactionType::Deposit { amount, .. } = disputed_tx {
-            self.held -= amount;
-            self.total -= amount;
-            self.locked = true;
-        }
+        let (available, held) = (
+            BookAccount::ClientAvailable(self.client),
+            BookAccount::ClientHeld(self.client),
+        );
+        let entries = match *disputed_tx {
+            TransactionType::Deposit { amount, .. } => vec![
+                Entry::new(held, -amount),
+                Entry::new(BookAccount::ChargebackLoss, amount),
+            ],
+            // The withdrawal is reversed, return the funds to the client.
+            TransactionType::Withdrawal { amount, .. } => vec![
+                Entry::new(held, -amount),
+                Entry::new(available, amount),
+                Entry::new(BookAccount::DisputedWithdrawals, amount),
+                Entry::new(BookAccount::ChargebackLoss, -amount),
+            ],
+            _ => return Ok(Vec::new()),
+        };
+        self.locked = true;
 
-        Ok(())
+        Ok(entries)
     }
 }
 
@@ -330,4 +695,222 @@ mod tests {
         assert_eq!(account.held, "0.0".parse().unwrap());
         assert_eq!(account.total, "0.0".parse().unwrap());
     }
+
+    fn withdrawal_dispute_account() -> super::ClientAccount {
+        let config = super::ClientConfig {
+            withdrawal_disputes: super::WithdrawalDisputePolicy::Support,
+            ..Default::default()
+        };
+        let mut account = super::ClientAccount::with_config(1, config);
+        let tx = super::TransactionType::Deposit {
+            client: 1,
+            tx: 1,
+            amount: "2.0".parse().unwrap(),
+        };
+        account.process_transaction(tx).unwrap();
+        let tx = super::TransactionType::Withdrawal {
+            client: 1,
+            tx: 2,
+            amount: "1.5".parse().unwrap(),
+        };
+        account.process_transaction(tx).unwrap();
+        account
+    }
+
+    #[test]
+    fn check_withdrawal_dispute_rejected() {
+        let mut account = super::ClientAccount::new(1);
+        let tx = super::TransactionType::Deposit {
+            client: 1,
+            tx: 1,
+            amount: "2.0".parse().unwrap(),
+        };
+        account.process_transaction(tx).unwrap();
+        let tx = super::TransactionType::Withdrawal {
+            client: 1,
+            tx: 2,
+            amount: "1.5".parse().unwrap(),
+        };
+        account.process_transaction(tx).unwrap();
+
+        let tx = super::TransactionType::Dispute { client: 1, tx: 2 };
+        let err = account.process_transaction(tx).unwrap_err();
+        assert!(matches!(
+            err,
+            super::ClientErr::WithdrawalDisputeNotSupported
+        ));
+        assert_eq!(account.available, "0.5".parse().unwrap());
+        assert_eq!(account.held, "0.0".parse().unwrap());
+        assert_eq!(account.total, "0.5".parse().unwrap());
+
+        // Nothing to resolve.
+        let tx = super::TransactionType::Resolve { client: 1, tx: 2 };
+        account.process_transaction(tx).unwrap_err();
+    }
+
+    #[test]
+    fn check_withdrawal_dispute_resolve() {
+        let mut account = withdrawal_dispute_account();
+
+        // Valid dispute credits the withdrawn amount as held.
+        let tx = super::TransactionType::Dispute { client: 1, tx: 2 };
+        account.process_transaction(tx).unwrap();
+        assert_eq!(account.available, "0.5".parse().unwrap());
+        assert_eq!(account.held, "1.5".parse().unwrap());
+        assert_eq!(account.total, "2.0".parse().unwrap());
+
+        // Resolve confirms the withdrawal.
+        let tx = super::TransactionType::Resolve { client: 1, tx: 2 };
+        account.process_transaction(tx).unwrap();
+        assert_eq!(account.available, "0.5".parse().unwrap());
+        assert_eq!(account.held, "0.0".parse().unwrap());
+        assert_eq!(account.total, "0.5".parse().unwrap());
+        assert!(!account.is_locked());
+    }
+
+    #[test]
+    fn check_withdrawal_dispute_chargeback() {
+        let mut account = withdrawal_dispute_account();
+
+        let tx = super::TransactionType::Dispute { client: 1, tx: 2 };
+        account.process_transaction(tx).unwrap();
+
+        // Chargeback returns the funds to the client.
+        let tx = super::TransactionType::Chargeback { client: 1, tx: 2 };
+        account.process_transaction(tx).unwrap();
+        assert_eq!(account.available, "2.0".parse().unwrap());
+        assert_eq!(account.held, "0.0".parse().unwrap());
+        assert_eq!(account.total, "2.0".parse().unwrap());
+        assert!(account.is_locked());
+    }
+
+    #[test]
+    fn check_dispute_lifecycle() {
+        use super::DisputeState;
+
+        let mut account = super::ClientAccount::new(1);
+        let tx = super::TransactionType::Deposit {
+            client: 1,
+            tx: 1,
+            amount: "1.0".parse().unwrap(),
+        };
+        account.process_transaction(tx).unwrap();
+        assert_eq!(account.dispute_state(1), Some(DisputeState::Processed));
+        assert_eq!(account.dispute_state(2), None);
+
+        // Cannot resolve an undisputed transaction.
+        let tx = super::TransactionType::Resolve { client: 1, tx: 1 };
+        let err = account.process_transaction(tx).unwrap_err();
+        assert!(matches!(
+            err,
+            super::ClientErr::InvalidDisputeState(DisputeState::Processed)
+        ));
+
+        let tx = super::TransactionType::Dispute { client: 1, tx: 1 };
+        account.process_transaction(tx).unwrap();
+        assert_eq!(account.dispute_state(1), Some(DisputeState::Disputed));
+
+        let tx = super::TransactionType::Resolve { client: 1, tx: 1 };
+        account.process_transaction(tx).unwrap();
+        assert_eq!(account.dispute_state(1), Some(DisputeState::Resolved));
+
+        // Re-dispute denied by default.
+        let tx = super::TransactionType::Dispute { client: 1, tx: 1 };
+        let err = account.process_transaction(tx).unwrap_err();
+        assert!(matches!(
+            err,
+            super::ClientErr::InvalidDisputeState(DisputeState::Resolved)
+        ));
+        assert_eq!(account.available, "1.0".parse().unwrap());
+        assert_eq!(account.held, "0.0".parse().unwrap());
+    }
+
+    #[test]
+    fn check_redispute_allowed() {
+        use super::DisputeState;
+
+        let config = super::ClientConfig {
+            redispute: super::RedisputePolicy::Allow,
+            ..Default::default()
+        };
+        let mut account = super::ClientAccount::with_config(1, config);
+        let tx = super::TransactionType::Deposit {
+            client: 1,
+            tx: 1,
+            amount: "1.0".parse().unwrap(),
+        };
+        account.process_transaction(tx).unwrap();
+
+        let tx = super::TransactionType::Dispute { client: 1, tx: 1 };
+        account.process_transaction(tx).unwrap();
+        let tx = super::TransactionType::Resolve { client: 1, tx: 1 };
+        account.process_transaction(tx).unwrap();
+
+        // Re-dispute and chargeback.
+        let tx = super::TransactionType::Dispute { client: 1, tx: 1 };
+        account.process_transaction(tx).unwrap();
+        assert_eq!(account.dispute_state(1), Some(DisputeState::Disputed));
+        assert_eq!(account.held, "1.0".parse().unwrap());
+
+        let tx = super::TransactionType::Chargeback { client: 1, tx: 1 };
+        account.process_transaction(tx).unwrap();
+        assert_eq!(account.dispute_state(1), Some(DisputeState::ChargedBack));
+        assert_eq!(account.total, "0.0".parse().unwrap());
+        assert!(account.is_locked());
+    }
+
+    #[test]
+    fn check_ledger_events() {
+        use crate::ledger::EventKind;
+
+        let mut account = super::ClientAccount::new(1);
+        for tx in [
+            super::TransactionType::Deposit {
+                client: 1,
+                tx: 1,
+                amount: "3.0".parse().unwrap(),
+            },
+            super::TransactionType::Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: "1.0".parse().unwrap(),
+            },
+            super::TransactionType::Dispute { client: 1, tx: 1 },
+            super::TransactionType::Chargeback { client: 1, tx: 1 },
+        ] {
+            account.process_transaction(tx).unwrap();
+        }
+
+        // Rejected transactions are not recorded.
+        let tx = super::TransactionType::Deposit {
+            client: 1,
+            tx: 3,
+            amount: "1.0".parse().unwrap(),
+        };
+        account.process_transaction(tx).unwrap_err();
+
+        let kinds: Vec<_> = account.events().iter().map(|e| (e.seq, e.kind)).collect();
+        assert_eq!(
+            kinds,
+            [
+                (1, EventKind::Deposit),
+                (2, EventKind::Withdrawal),
+                (3, EventKind::Hold),
+                (4, EventKind::Chargeback),
+            ]
+        );
+
+        let balances = account.balances_at(3);
+        assert_eq!(balances.available, "-1.0".parse().unwrap());
+        assert_eq!(balances.held, "3.0".parse().unwrap());
+        assert_eq!(balances.total, "2.0".parse().unwrap());
+        assert!(!balances.locked);
+        assert_eq!(account.balances_at(0), Default::default());
+
+        let (event, balances) = account.statement().last().unwrap();
+        assert_eq!(event.seq, 4);
+        assert_eq!(balances, account.balances_at(4));
+        assert_eq!(balances.total, account.total());
+        assert!(balances.locked);
+    }
 }
@@ -1,62 +1,379 @@
-use crate::{client::ClientAccount, types::TransactionType};
+use crate::{
+    books::TrialBalance,
+    client::{BalanceDelta, ClientAccount, ClientConfig, DisputeState},
+    error::{EngineErr, EngineErrKind},
+    journal::JournalEntry,
+    output::{self, AccountSnapshot, OutputOptions},
+    snapshot::EngineSnapshot,
+    storage::{Accounts, MemoryStorage, Storage, StorageErr},
+    types::TransactionType,
+};
 
-use std::collections::HashMap;
+use std::{borrow::Cow, collections::HashSet, io::Write, str::FromStr};
 
-pub struct PaymentEngine {
-    accounts: HashMap<u16, ClientAccount>,
+/// Order of the accounts in the serialized output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AccountOrder {
+    /// Ascending client ID.
+    #[default]
+    ClientId,
+    /// Descending client ID.
+    ClientIdDesc,
+    /// Descending total funds, ties broken by ascending client ID.
+    TotalDesc,
+}
+
+impl FromStr for AccountOrder {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "client" => Ok(Self::ClientId),
+            "client-desc" => Ok(Self::ClientIdDesc),
+            "total-desc" => Ok(Self::TotalDesc),
+            _ => Err("Unknown account order"),
+        }
+    }
+}
+
+/// Processes transactions and maintains the state of the client accounts.
+#[derive(Default)]
+pub struct PaymentEngine<S = MemoryStorage> {
+    /// The accounts and the engine-wide registry of processed transaction IDs
+    /// and the client owning them.
+    storage: S,
+    /// Configuration applied to every account.
+    config: ClientConfig,
+}
+
+/// True if the transaction registers its ID for the client.
+pub(crate) fn registers_id(tx: &TransactionType) -> bool {
+    matches!(
+        tx,
+        TransactionType::Deposit { .. } | TransactionType::Withdrawal { .. }
+    )
+}
+
+/// The error of a transaction whose ID is owned by another client.
+pub(crate) fn owner_mismatch(tx: &TransactionType, owner: u16) -> EngineErr {
+    let kind = if registers_id(tx) {
+        EngineErrKind::TransactionIdInUse { owner }
+    } else {
+        EngineErrKind::TransactionOwnerMismatch { owner }
+    };
+    EngineErr::new(tx.client_id(), tx.transaction_id(), kind)
 }
 
 impl PaymentEngine {
     /// Constructs a new [`PaymentEngine`].
     pub fn new() -> Self {
-        Self {
-            accounts: HashMap::new(),
-        }
+        Self::with_config(ClientConfig::default())
+    }
+
+    /// Constructs a new [`PaymentEngine`] creating accounts with the given configuration.
+    pub fn with_config(config: ClientConfig) -> Self {
+        Self::with_storage(MemoryStorage::new(), config)
+    }
+}
+
+impl<S: Storage> PaymentEngine<S> {
+    /// Constructs a new [`PaymentEngine`] keeping its state in the given storage.
+    pub fn with_storage(storage: S, config: ClientConfig) -> Self {
+        Self { storage, config }
     }
 
-    /// Process the given transaction.
-    pub fn process_transaction(&mut self, tx: TransactionType) {
+    /// Process the given transaction, returning the change of the balances of the account.
+    pub fn process_transaction(&mut self, tx: TransactionType) -> Result<BalanceDelta, EngineErr> {
         let client_id = tx.client_id();
+        let tx_id = tx.transaction_id();
+        let storage_err = |err: StorageErr| EngineErr::new(client_id, tx_id, err);
 
-        let account = self
-            .accounts
-            .entry(client_id)
-            .or_insert_with(|| ClientAccount::new(client_id));
+        // Transaction IDs are globally unique, and disputes may only reference
+        // transactions owned by the same client.
+        let owner = self.storage.tx_owner(tx_id).map_err(storage_err)?;
+        if let Some(owner) = owner {
+            if owner != client_id {
+                return Err(owner_mismatch(&tx, owner));
+            }
+        }
 
-        if let Err(err) = account.process_transaction(tx) {
-            log::error!("[{}] Error processing transaction: {:?}", client_id, err);
+        // The ID is registered before the account is taken, so no stored account
+        // holds a transaction whose owner is unknown and a failed registration
+        // leaves the account as it was. It is unregistered if the transaction fails.
+        let registers_id = registers_id(&tx) && owner.is_none();
+        if registers_id {
+            self.storage
+                .register_tx(tx_id, client_id)
+                .map_err(storage_err)?;
         }
+
+        let mut account = self
+            .storage
+            .take_account(client_id)
+            .map_err(storage_err)?
+            .unwrap_or_else(|| ClientAccount::new(client_id));
+        account.config = self.config;
+
+        let result = account.process_transaction(tx);
+        self.storage.put_account(account).map_err(storage_err)?;
+        if registers_id && result.is_err() {
+            self.storage.unregister_tx(tx_id).map_err(storage_err)?;
+        }
+
+        result.map_err(|err| EngineErr::new(client_id, tx_id, err))
     }
 
-    /// Serialize the current state of the accounts.
-    pub fn serialize(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let mut writer = csv::Writer::from_writer(std::io::stdout());
+    /// Rebuild the state by applying the entries of a [`Journal`](crate::journal::Journal).
+    ///
+    /// Every entry must be accepted again with the recorded change of the balances.
+    pub fn replay_journal<'a>(
+        &mut self,
+        entries: impl IntoIterator<Item = &'a JournalEntry>,
+    ) -> Result<(), EngineErr> {
+        for entry in entries {
+            let tx = &entry.transaction;
+            let delta = self.process_transaction(tx.clone())?;
+            if delta != entry.delta {
+                let reason = format!("{}:{} replays with {delta:?}", entry.source, entry.line);
+                return Err(EngineErr::new(
+                    tx.client_id(),
+                    tx.transaction_id(),
+                    StorageErr::Corrupt(reason),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The account of the given client, if any transaction was processed for it.
+    pub fn account(&self, client: u16) -> Result<Option<Cow<'_, ClientAccount>>, StorageErr> {
+        self.storage.account(client)
+    }
+
+    /// Iterate over all accounts, in no particular order.
+    pub fn accounts(&self) -> Accounts<'_> {
+        self.storage.accounts()
+    }
+
+    /// The client owning the given transaction ID, if a deposit or withdrawal with it
+    /// was processed.
+    pub fn tx_owner(&self, tx: u32) -> Result<Option<u16>, StorageErr> {
+        self.storage.tx_owner(tx)
+    }
+
+    /// The dispute state of the given transaction, if it was processed.
+    pub fn dispute_state(&self, tx: u32) -> Result<Option<DisputeState>, StorageErr> {
+        let Some(client) = self.tx_owner(tx)? else {
+            return Ok(None);
+        };
+        Ok(self
+            .storage
+            .account(client)?
+            .and_then(|account| account.dispute_state(tx)))
+    }
+
+    /// The trial balance of the books beneath all accounts.
+    pub fn trial_balance(&self) -> Result<TrialBalance, StorageErr> {
+        let mut trial = TrialBalance::new();
+        for account in self.accounts() {
+            trial.add_account(&*account?);
+        }
+        Ok(trial)
+    }
 
-        writer.write_record(["client", "available", "held", "total", "locked"])?;
+    /// A snapshot of the complete state of the engine.
+    pub fn snapshot(&self) -> Result<EngineSnapshot, StorageErr> {
+        let accounts = self
+            .accounts()
+            .map(|account| account.map(Cow::into_owned))
+            .collect::<Result<_, _>>()?;
+        Ok(EngineSnapshot::new(accounts))
+    }
 
-        for (client, account) in &self.accounts {
-            writer
-                .write_record(&[
-                    client.to_string(),
-                    account.available.to_string(),
-                    account.held.to_string(),
-                    account.total.to_string(),
-                    account.locked.to_string(),
-                ])
-                .unwrap();
+    /// Load the accounts of the snapshot, replacing the accounts of the same clients.
+    ///
+    /// Fails without changing the engine if the snapshot is not
+    /// [valid](EngineSnapshot::validate) or uses a transaction ID owned by a client
+    /// not in the snapshot.
+    pub fn restore(&mut self, snapshot: EngineSnapshot) -> Result<(), StorageErr> {
+        snapshot.validate()?;
+        let clients: HashSet<_> = snapshot
+            .accounts
+            .iter()
+            .map(ClientAccount::client)
+            .collect();
+        for account in &snapshot.accounts {
+            for (tx, _) in account.transactions() {
+                let tx = tx.transaction_id();
+                match self.storage.tx_owner(tx)? {
+                    Some(owner) if !clients.contains(&owner) => {
+                        return Err(StorageErr::Corrupt(format!(
+                            "transaction {tx} of client {} is owned by client {owner}",
+                            account.client()
+                        )));
+                    }
+                    _ => {}
+                }
+            }
         }
 
+        for mut account in snapshot.accounts {
+            let client = account.client();
+
+            // The transactions of a replaced account are not owned by the client
+            // anymore, unless the snapshot has them as well.
+            let stale: Vec<_> = match self.storage.account(client)? {
+                Some(stored) => stored
+                    .transactions()
+                    .map(|(tx, _)| tx.transaction_id())
+                    .collect(),
+                None => Vec::new(),
+            };
+            for tx in stale {
+                if self.storage.tx_owner(tx)? == Some(client) {
+                    self.storage.unregister_tx(tx)?;
+                }
+            }
+
+            let tx_ids: Vec<_> = account
+                .transactions()
+                .map(|(tx, _)| tx.transaction_id())
+                .collect();
+            for tx in tx_ids {
+                self.storage.register_tx(tx, client)?;
+            }
+            account.config = self.config;
+            self.storage.put_account(account)?;
+        }
         Ok(())
     }
+
+    /// Persist all pending changes of the storage.
+    pub fn flush(&mut self) -> Result<(), StorageErr> {
+        self.storage.flush()
+    }
+
+    /// Snapshots of all accounts, ordered and formatted according to the options.
+    pub fn snapshots(&self, options: &OutputOptions) -> Result<Vec<AccountSnapshot>, StorageErr> {
+        // Sort on the unrounded totals, accounts are only loaded one at a time.
+        let mut snapshots = Vec::new();
+        for account in self.accounts() {
+            let account = account?;
+            snapshots.push((
+                account.total(),
+                AccountSnapshot::new(&account, &options.amounts),
+            ));
+        }
+
+        match options.order {
+            AccountOrder::ClientId => snapshots.sort_by_key(|(_, snapshot)| snapshot.client),
+            AccountOrder::ClientIdDesc => {
+                snapshots.sort_by_key(|(_, snapshot)| std::cmp::Reverse(snapshot.client))
+            }
+            AccountOrder::TotalDesc => snapshots.sort_by(|(a_total, a), (b_total, b)| {
+                b_total.cmp(a_total).then(a.client.cmp(&b.client))
+            }),
+        }
+
+        Ok(snapshots
+            .into_iter()
+            .map(|(_, snapshot)| snapshot)
+            .collect())
+    }
+
+    /// Serialize the current state of the accounts.
+    pub fn serialize<W: Write>(
+        &self,
+        writer: W,
+        options: &OutputOptions,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        output::write_snapshots(writer, &self.snapshots(options)?, options.format)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::TransactionType;
+    use crate::{client::ClientErr, types::TransactionType};
     use rust_decimal::Decimal;
+    use serde_json::json;
     use std::str::FromStr;
 
+    fn deposit(client: u16, tx: u32) -> TransactionType {
+        TransactionType::Deposit {
+            client,
+            tx,
+            amount: Decimal::ONE,
+        }
+    }
+
+    /// Storage whose registration of transaction IDs fails once `fail` is set.
+    struct FailingStorage {
+        storage: MemoryStorage,
+        fail: bool,
+    }
+
+    impl Storage for FailingStorage {
+        fn account(&self, client: u16) -> Result<Option<Cow<'_, ClientAccount>>, StorageErr> {
+            self.storage.account(client)
+        }
+
+        fn take_account(&mut self, client: u16) -> Result<Option<ClientAccount>, StorageErr> {
+            self.storage.take_account(client)
+        }
+
+        fn put_account(&mut self, account: ClientAccount) -> Result<(), StorageErr> {
+            self.storage.put_account(account)
+        }
+
+        fn accounts(&self) -> Accounts<'_> {
+            self.storage.accounts()
+        }
+
+        fn tx_owner(&self, tx: u32) -> Result<Option<u16>, StorageErr> {
+            self.storage.tx_owner(tx)
+        }
+
+        fn register_tx(&mut self, tx: u32, client: u16) -> Result<(), StorageErr> {
+            if self.fail {
+                return Err(StorageErr::Corrupt("registry unavailable".into()));
+            }
+            self.storage.register_tx(tx, client)
+        }
+
+        fn unregister_tx(&mut self, tx: u32) -> Result<(), StorageErr> {
+            self.storage.unregister_tx(tx)
+        }
+    }
+
+    #[test]
+    fn test_register_failure() {
+        let storage = FailingStorage {
+            storage: MemoryStorage::new(),
+            fail: false,
+        };
+        let mut engine = PaymentEngine::with_storage(storage, ClientConfig::default());
+        engine.process_transaction(deposit(1, 1)).unwrap();
+
+        // The account is kept as it was when the ID cannot be registered.
+        engine.storage.fail = true;
+        let err = engine.process_transaction(deposit(1, 2)).unwrap_err();
+        assert!(matches!(err.kind(), EngineErrKind::Storage(_)));
+        let account = engine.account(1).unwrap().unwrap();
+        assert_eq!(account.total, Decimal::ONE);
+
+        // A failed transaction does not keep its ID.
+        engine.storage.fail = false;
+        let withdrawal = TransactionType::Withdrawal {
+            client: 1,
+            tx: 3,
+            amount: Decimal::TEN,
+        };
+        engine.process_transaction(withdrawal).unwrap_err();
+        assert_eq!(engine.storage.tx_owner(3).unwrap(), None);
+        engine.process_transaction(deposit(1, 3)).unwrap();
+    }
+
     #[test]
     fn test_multiaccount_deposit() {
         let mut engine = PaymentEngine::new();
@@ -67,13 +384,13 @@ mod tests {
             amount: Decimal::from_str("1.0").unwrap(),
         };
 
-        engine.process_transaction(tx);
+        engine.process_transaction(tx).unwrap();
 
-        let account = engine.accounts.get(&1).unwrap();
+        let account = engine.account(1).unwrap().unwrap();
         assert_eq!(account.available, Decimal::from_str("1.0").unwrap());
         assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
         assert_eq!(account.total, Decimal::from_str("1.0").unwrap());
-        assert_eq!(account.locked, false);
+        assert!(!account.locked);
 
         let tx = TransactionType::Deposit {
             client: 2,
@@ -81,21 +398,21 @@ mod tests {
             amount: Decimal::from_str("4.0").unwrap(),
         };
 
-        engine.process_transaction(tx);
+        engine.process_transaction(tx).unwrap();
 
         // Account 1 unaffected.
-        let account = engine.accounts.get(&1).unwrap();
+        let account = engine.account(1).unwrap().unwrap();
         assert_eq!(account.available, Decimal::from_str("1.0").unwrap());
         assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
         assert_eq!(account.total, Decimal::from_str("1.0").unwrap());
-        assert_eq!(account.locked, false);
+        assert!(!account.locked);
 
         // Account 2 updated.
-        let account = engine.accounts.get(&2).unwrap();
+        let account = engine.account(2).unwrap().unwrap();
         assert_eq!(account.available, Decimal::from_str("4.0").unwrap());
         assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
         assert_eq!(account.total, Decimal::from_str("4.0").unwrap());
-        assert_eq!(account.locked, false);
+        assert!(!account.locked);
     }
 
     #[test]
@@ -107,37 +424,397 @@ mod tests {
             tx: 1,
             amount: Decimal::from_str("1.0").unwrap(),
         };
-        engine.process_transaction(tx);
+        engine.process_transaction(tx).unwrap();
         let tx = TransactionType::Deposit {
             client: 2,
             tx: 2,
             amount: Decimal::from_str("4.0").unwrap(),
         };
-        engine.process_transaction(tx);
+        engine.process_transaction(tx).unwrap();
 
         let tx = TransactionType::Withdrawal {
             client: 1,
             tx: 3,
             amount: Decimal::from_str("0.5").unwrap(),
         };
-        engine.process_transaction(tx);
+        engine.process_transaction(tx).unwrap();
         let tx = TransactionType::Withdrawal {
             client: 2,
             tx: 4,
             amount: Decimal::from_str("1.0").unwrap(),
         };
-        engine.process_transaction(tx);
+        engine.process_transaction(tx).unwrap();
 
-        let account = engine.accounts.get(&1).unwrap();
+        let account = engine.account(1).unwrap().unwrap();
         assert_eq!(account.available, Decimal::from_str("0.5").unwrap());
         assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
         assert_eq!(account.total, Decimal::from_str("0.5").unwrap());
-        assert_eq!(account.locked, false);
+        assert!(!account.locked);
 
-        let account = engine.accounts.get(&2).unwrap();
+        let account = engine.account(2).unwrap().unwrap();
         assert_eq!(account.available, Decimal::from_str("3.0").unwrap());
         assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
         assert_eq!(account.total, Decimal::from_str("3.0").unwrap());
-        assert_eq!(account.locked, false);
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn test_tx_id_unique_across_clients() {
+        let mut engine = PaymentEngine::new();
+
+        let tx = TransactionType::Deposit {
+            client: 1,
+            tx: 7,
+            amount: Decimal::from_str("1.0").unwrap(),
+        };
+        engine.process_transaction(tx).unwrap();
+
+        // Same tx id reused by another client.
+        let tx = TransactionType::Deposit {
+            client: 2,
+            tx: 7,
+            amount: Decimal::from_str("4.0").unwrap(),
+        };
+        let err = engine.process_transaction(tx).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            EngineErrKind::TransactionIdInUse { owner: 1 }
+        ));
+        assert_eq!(err.client(), 2);
+        assert_eq!(err.tx(), 7);
+        assert!(engine.account(2).unwrap().is_none());
+
+        // Same tx id reused by the same client.
+        let tx = TransactionType::Withdrawal {
+            client: 1,
+            tx: 7,
+            amount: Decimal::from_str("1.0").unwrap(),
+        };
+        let err = engine.process_transaction(tx).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            EngineErrKind::Client(ClientErr::AlreadyProcessed)
+        ));
+
+        let account = engine.account(1).unwrap().unwrap();
+        assert_eq!(account.available, Decimal::from_str("1.0").unwrap());
+        assert_eq!(account.total, Decimal::from_str("1.0").unwrap());
+    }
+
+    #[test]
+    fn test_dispute_foreign_tx() {
+        let mut engine = PaymentEngine::new();
+
+        let tx = TransactionType::Deposit {
+            client: 1,
+            tx: 1,
+            amount: Decimal::from_str("1.0").unwrap(),
+        };
+        engine.process_transaction(tx).unwrap();
+
+        for tx in [
+            TransactionType::Dispute { client: 2, tx: 1 },
+            TransactionType::Resolve { client: 2, tx: 1 },
+            TransactionType::Chargeback { client: 2, tx: 1 },
+        ] {
+            let err = engine.process_transaction(tx).unwrap_err();
+            assert!(matches!(
+                err.kind(),
+                EngineErrKind::TransactionOwnerMismatch { owner: 1 }
+            ));
+        }
+
+        let account = engine.account(1).unwrap().unwrap();
+        assert_eq!(account.available, Decimal::from_str("1.0").unwrap());
+        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
+        assert!(engine.account(2).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_dispute_state() {
+        let mut engine = PaymentEngine::new();
+
+        let tx = TransactionType::Deposit {
+            client: 2,
+            tx: 5,
+            amount: Decimal::from_str("1.0").unwrap(),
+        };
+        engine.process_transaction(tx).unwrap();
+        assert_eq!(
+            engine.dispute_state(5).unwrap(),
+            Some(DisputeState::Processed)
+        );
+        assert_eq!(engine.dispute_state(6).unwrap(), None);
+
+        let tx = TransactionType::Dispute { client: 2, tx: 5 };
+        engine.process_transaction(tx).unwrap();
+        assert_eq!(
+            engine.dispute_state(5).unwrap(),
+            Some(DisputeState::Disputed)
+        );
+    }
+
+    #[test]
+    fn test_serialize_order() {
+        let mut engine = PaymentEngine::new();
+
+        for (client, tx, amount) in [(3, 1, "1.0"), (1, 2, "5.0"), (2, 3, "1.0")] {
+            let tx = TransactionType::Deposit {
+                client,
+                tx,
+                amount: Decimal::from_str(amount).unwrap(),
+            };
+            engine.process_transaction(tx).unwrap();
+        }
+
+        let clients = |order| {
+            let options = OutputOptions {
+                order,
+                ..Default::default()
+            };
+            let mut out = Vec::new();
+            engine.serialize(&mut out, &options).unwrap();
+            String::from_utf8(out)
+                .unwrap()
+                .lines()
+                .skip(1)
+                .map(|line| line.split(',').next().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(clients(AccountOrder::ClientId), ["1", "2", "3"]);
+        assert_eq!(clients(AccountOrder::ClientIdDesc), ["3", "2", "1"]);
+        assert_eq!(clients(AccountOrder::TotalDesc), ["1", "2", "3"]);
+    }
+
+    #[test]
+    fn test_serialize_fixed_precision() {
+        let mut engine = PaymentEngine::new();
+
+        let tx = TransactionType::Deposit {
+            client: 1,
+            tx: 1,
+            amount: Decimal::from_str("1.5").unwrap(),
+        };
+        engine.process_transaction(tx).unwrap();
+        let tx = TransactionType::Dispute { client: 1, tx: 1 };
+        engine.process_transaction(tx).unwrap();
+
+        let mut out = Vec::new();
+        engine
+            .serialize(&mut out, &OutputOptions::default())
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked\n1,0.0000,1.5000,1.5000,false\n"
+        );
+    }
+
+    #[test]
+    fn test_file_storage_survives_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let open = || {
+            let storage = crate::storage::FileStorage::open(dir.path()).unwrap();
+            PaymentEngine::with_storage(storage, ClientConfig::default())
+        };
+
+        let mut engine = open();
+        let tx = TransactionType::Deposit {
+            client: 1,
+            tx: 1,
+            amount: Decimal::from_str("2.0").unwrap(),
+        };
+        engine.process_transaction(tx).unwrap();
+        engine.flush().unwrap();
+        drop(engine);
+
+        let mut engine = open();
+        // The transaction ID is still owned by client 1.
+        let tx = TransactionType::Deposit {
+            client: 2,
+            tx: 1,
+            amount: Decimal::from_str("1.0").unwrap(),
+        };
+        let err = engine.process_transaction(tx).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            EngineErrKind::TransactionIdInUse { owner: 1 }
+        ));
+
+        let tx = TransactionType::Dispute { client: 1, tx: 1 };
+        engine.process_transaction(tx).unwrap();
+        assert_eq!(
+            engine.dispute_state(1).unwrap(),
+            Some(DisputeState::Disputed)
+        );
+
+        let account = engine.account(1).unwrap().unwrap();
+        assert_eq!(account.available(), Decimal::ZERO);
+        assert_eq!(account.held(), Decimal::from_str("2.0").unwrap());
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut engine = PaymentEngine::new();
+        for tx in [
+            TransactionType::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Decimal::from_str("2.0").unwrap(),
+            },
+            TransactionType::Deposit {
+                client: 2,
+                tx: 2,
+                amount: Decimal::from_str("1.0").unwrap(),
+            },
+            TransactionType::Dispute { client: 2, tx: 2 },
+            TransactionType::Chargeback { client: 2, tx: 2 },
+        ] {
+            engine.process_transaction(tx).unwrap();
+        }
+
+        let mut out = Vec::new();
+        engine.snapshot().unwrap().write(&mut out).unwrap();
+        let snapshot = EngineSnapshot::read(out.as_slice()).unwrap();
+
+        let mut engine = PaymentEngine::new();
+        engine.restore(snapshot).unwrap();
+
+        // A dispute of a transaction processed before the snapshot.
+        let tx = TransactionType::Dispute { client: 1, tx: 1 };
+        engine.process_transaction(tx).unwrap();
+        let account = engine.account(1).unwrap().unwrap();
+        assert_eq!(account.held(), Decimal::from_str("2.0").unwrap());
+
+        // The transaction IDs and locks are restored.
+        let tx = TransactionType::Deposit {
+            client: 3,
+            tx: 2,
+            amount: Decimal::from_str("1.0").unwrap(),
+        };
+        let err = engine.process_transaction(tx).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            EngineErrKind::TransactionIdInUse { owner: 2 }
+        ));
+        assert!(engine.account(2).unwrap().unwrap().is_locked());
+        assert_eq!(
+            engine.dispute_state(2).unwrap(),
+            Some(DisputeState::ChargedBack)
+        );
+    }
+
+    #[test]
+    fn test_snapshot_validate() {
+        let mut engine = PaymentEngine::new();
+        engine.process_transaction(deposit(1, 1)).unwrap();
+        engine.process_transaction(deposit(2, 2)).unwrap();
+        let mut out = Vec::new();
+        engine.snapshot().unwrap().write(&mut out).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+
+        let read = |edit: fn(&mut serde_json::Value)| {
+            let mut json = json.clone();
+            edit(&mut json);
+            EngineSnapshot::read(json.to_string().as_bytes())
+        };
+        assert!(read(|_| {}).is_ok());
+
+        let edits: [fn(&mut serde_json::Value); 4] = [
+            // A transaction without a dispute state.
+            |json| json["accounts"][0]["dispute_state"] = json!({}),
+            // A dispute state without a transaction.
+            |json| json["accounts"][0]["dispute_state"]["7"] = json!("Processed"),
+            // A transaction ID used by two clients.
+            |json| {
+                let tx = json["accounts"][0]["processed_tx"]["1"].clone();
+                json["accounts"][1]["processed_tx"]["1"] = tx;
+                json["accounts"][1]["dispute_state"]["1"] = json!("Processed");
+            },
+            // Two accounts of the same client.
+            |json| json["accounts"][1]["client"] = json!(1),
+        ];
+        for edit in edits {
+            let err = read(edit).unwrap_err();
+            assert_eq!(err.code(), "storage_corrupt", "{err}");
+        }
+    }
+
+    #[test]
+    fn test_restore_registry() {
+        let mut engine = PaymentEngine::new();
+        engine.process_transaction(deposit(1, 1)).unwrap();
+        engine.process_transaction(deposit(2, 2)).unwrap();
+
+        // Client 1 is replaced, its previous transaction is not owned anymore.
+        let mut account = ClientAccount::new(1);
+        account.process_transaction(deposit(1, 3)).unwrap();
+        engine.restore(EngineSnapshot::new(vec![account])).unwrap();
+        assert_eq!(engine.tx_owner(1).unwrap(), None);
+        assert_eq!(engine.tx_owner(3).unwrap(), Some(1));
+        engine.process_transaction(deposit(3, 1)).unwrap();
+
+        // A transaction ID owned by a client not in the snapshot.
+        let mut account = ClientAccount::new(4);
+        account.process_transaction(deposit(4, 2)).unwrap();
+        let err = engine
+            .restore(EngineSnapshot::new(vec![account]))
+            .unwrap_err();
+        assert_eq!(err.code(), "storage_corrupt");
+        assert_eq!(engine.tx_owner(2).unwrap(), Some(2));
+        assert!(engine.account(4).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_snapshot_version() {
+        let err = EngineSnapshot::read(r#"{"version":0,"accounts":[]}"#.as_bytes()).unwrap_err();
+        assert_eq!(err.code(), "storage_corrupt");
+
+        // Accounts of version 1 have no ledger and are refused by their version.
+        let v1 = r#"{"version":1,"accounts":[{"client":1,"available":"1","held":"0",
+            "total":"1","locked":false,"processed_tx":{},"dispute_state":{}}]}"#;
+        let err = EngineSnapshot::read(v1.as_bytes()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "corrupt storage: unsupported snapshot version 1"
+        );
+    }
+
+    #[test]
+    fn test_replay_journal() {
+        let mut engine = PaymentEngine::new();
+        let mut entries = Vec::new();
+        for (line, tx) in [
+            TransactionType::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Decimal::from_str("2.0").unwrap(),
+            },
+            TransactionType::Dispute { client: 1, tx: 1 },
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let delta = engine.process_transaction(tx.clone()).unwrap();
+            entries.push(JournalEntry {
+                source: "a.csv".into(),
+                line: line as u64 + 2,
+                transaction: tx,
+                delta,
+            });
+        }
+        assert_eq!(entries[1].delta.held, Decimal::from_str("2.0").unwrap());
+
+        let mut replayed = PaymentEngine::new();
+        replayed.replay_journal(&entries).unwrap();
+        assert_eq!(
+            replayed.snapshots(&OutputOptions::default()).unwrap(),
+            engine.snapshots(&OutputOptions::default()).unwrap()
+        );
+
+        // An entry that does not replay with the recorded change.
+        entries[1].delta.held = Decimal::ONE;
+        let err = PaymentEngine::new().replay_journal(&entries).unwrap_err();
+        assert_eq!(err.code(), "storage_corrupt");
     }
 }
@@ -0,0 +1,159 @@
+use crate::{client::ClientErr, storage::StorageErr};
+
+use std::fmt;
+
+/// Reasons a row cannot be converted into a [`TransactionType`](crate::types::TransactionType).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErr {
+    /// The transaction type is not known.
+    UnknownType,
+    /// A deposit or withdrawal has no amount.
+    MissingAmount,
+    /// The amount is not a valid decimal.
+    InvalidDecimal,
+    /// The amount has more decimal places than the engine supports.
+    InvalidPrecision,
+}
+
+impl ParseErr {
+    /// A stable, machine-readable code of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UnknownType => "unknown_type",
+            Self::MissingAmount => "missing_amount",
+            Self::InvalidDecimal => "invalid_decimal",
+            Self::InvalidPrecision => "invalid_precision",
+        }
+    }
+}
+
+impl fmt::Display for ParseErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownType => write!(f, "unknown transaction type"),
+            Self::MissingAmount => write!(f, "no amount provided"),
+            Self::InvalidDecimal => write!(f, "invalid decimal"),
+            Self::InvalidPrecision => write!(f, "invalid precision"),
+        }
+    }
+}
+
+impl std::error::Error for ParseErr {}
+
+/// The reason a transaction was rejected.
+#[derive(Debug)]
+pub enum EngineErrKind {
+    /// The row could not be parsed into a transaction.
+    Parse(ParseErr),
+    /// The transaction ID was already used by another client.
+    TransactionIdInUse { owner: u16 },
+    /// The referenced transaction belongs to another client.
+    TransactionOwnerMismatch { owner: u16 },
+    /// The client account rejected the transaction.
+    Client(ClientErr),
+    /// The state of the engine could not be loaded or stored.
+    Storage(StorageErr),
+}
+
+impl EngineErrKind {
+    /// A stable, machine-readable code of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Parse(err) => err.code(),
+            Self::TransactionIdInUse { .. } => "transaction_id_in_use",
+            Self::TransactionOwnerMismatch { .. } => "transaction_owner_mismatch",
+            Self::Client(err) => err.code(),
+            Self::Storage(err) => err.code(),
+        }
+    }
+}
+
+impl fmt::Display for EngineErrKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse(err) => write!(f, "{err}"),
+            Self::TransactionIdInUse { owner } => {
+                write!(f, "transaction id already used by client {owner}")
+            }
+            Self::TransactionOwnerMismatch { owner } => {
+                write!(f, "referenced transaction belongs to client {owner}")
+            }
+            Self::Client(err) => write!(f, "{err}"),
+            Self::Storage(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+/// A rejected transaction, with the client and transaction it refers to.
+#[derive(Debug)]
+pub struct EngineErr {
+    client: u16,
+    tx: u32,
+    kind: EngineErrKind,
+}
+
+impl EngineErr {
+    /// Constructs a new [`EngineErr`] for the given client and transaction.
+    pub fn new(client: u16, tx: u32, kind: impl Into<EngineErrKind>) -> Self {
+        Self {
+            client,
+            tx,
+            kind: kind.into(),
+        }
+    }
+
+    /// The client ID of the rejected transaction.
+    pub fn client(&self) -> u16 {
+        self.client
+    }
+
+    /// The transaction ID of the rejected transaction.
+    pub fn tx(&self) -> u32 {
+        self.tx
+    }
+
+    /// The reason of the rejection.
+    pub fn kind(&self) -> &EngineErrKind {
+        &self.kind
+    }
+
+    /// A stable, machine-readable code of the reason.
+    pub fn code(&self) -> &'static str {
+        self.kind.code()
+    }
+}
+
+impl fmt::Display for EngineErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[client {} tx {}] {}", self.client, self.tx, self.kind)
+    }
+}
+
+impl std::error::Error for EngineErr {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            EngineErrKind::Parse(err) => Some(err),
+            EngineErrKind::Client(err) => Some(err),
+            EngineErrKind::Storage(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ParseErr> for EngineErrKind {
+    fn from(err: ParseErr) -> Self {
+        Self::Parse(err)
+    }
+}
+
+impl From<ClientErr> for EngineErrKind {
+    fn from(err: ClientErr) -> Self {
+        Self::Client(err)
+    }
+}
+
+impl From<StorageErr> for EngineErrKind {
+    fn from(err: StorageErr) -> Self {
+        Self::Storage(err)
+    }
+}
@@ -0,0 +1,461 @@
+use crate::{
+    client::ClientErr,
+    error::EngineErrKind,
+    input::{self, InputFormat},
+    output::{AccountSnapshot, AmountFormat},
+    server::read_bounded_line,
+    shared::SharedEngine,
+    stream::RowErr,
+};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+
+/// Largest accepted request body, in bytes.
+const MAX_BODY: usize = 64 * 1024;
+/// Largest accepted request line, in bytes.
+const MAX_REQUEST_LINE: u64 = 8 * 1024;
+/// Largest accepted size of all header lines, in bytes, not counting the empty
+/// line ending them.
+const MAX_HEADER_SIZE: u64 = 32 * 1024;
+/// Largest accepted number of headers.
+const MAX_HEADERS: usize = 100;
+
+/// The HTTP status of a refused transaction.
+pub fn status(err: &RowErr) -> u16 {
+    let kind = match err {
+        RowErr::Input(_) => return 400,
+        RowErr::Rejected(err) => err.kind(),
+    };
+
+    match kind {
+        EngineErrKind::Parse(_) => 400,
+        EngineErrKind::TransactionIdInUse { .. } => 409,
+        EngineErrKind::TransactionOwnerMismatch { .. } => 403,
+        EngineErrKind::Client(err) => match err {
+            ClientErr::AccountLocked => 423,
+            ClientErr::DisputedTransactionNotFound => 404,
+            ClientErr::AlreadyProcessed | ClientErr::InvalidDisputeState(_) => 409,
+            ClientErr::InsufficientFunds | ClientErr::WithdrawalDisputeNotSupported => 422,
+            ClientErr::InvariantViolated(_) => 500,
+        },
+        EngineErrKind::Storage(_) => 500,
+    }
+}
+
+/// The reason phrase of the HTTP status.
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        422 => "Unprocessable Entity",
+        423 => "Locked",
+        431 => "Request Header Fields Too Large",
+        501 => "Not Implemented",
+        _ => "Internal Server Error",
+    }
+}
+
+/// An HTTP response with a JSON body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: Value,
+}
+
+impl Response {
+    fn ok(body: impl Serialize) -> Self {
+        Self {
+            status: 200,
+            body: serde_json::to_value(body).expect("responses serialize to JSON"),
+        }
+    }
+
+    fn error(status: u16, code: &str, reason: impl ToString) -> Self {
+        Self {
+            status,
+            body: json!({ "code": code, "reason": reason.to_string() }),
+        }
+    }
+
+    /// Write the response, closing the connection after it.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let body = self.body.to_string();
+        write!(
+            writer,
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            self.status,
+            reason_phrase(self.status),
+            body.len()
+        )?;
+        writer.flush()
+    }
+}
+
+/// Answer a request to the API.
+///
+/// - `POST /transactions` applies the transaction in the body, a JSON object in
+///   the flat CSV shape or in the shape of a serialized `TransactionType`.
+/// - `GET /accounts` returns all accounts, ordered by client ID.
+/// - `GET /accounts/{client}` returns the account of a client.
+/// - `GET /transactions/{tx}` returns the owner and dispute state of a transaction.
+pub fn route(engine: &SharedEngine, method: &str, target: &str, body: &str) -> Response {
+    let amounts = AmountFormat::default();
+    let path = target.split('?').next().unwrap_or_default();
+    let segments: Vec<_> = path.trim_matches('/').split('/').collect();
+
+    match (method, segments.as_slice()) {
+        ("POST", ["transactions"]) => {
+            let tx = match input::parse_row(body, InputFormat::JsonLines) {
+                Ok(tx) => tx,
+                Err(err) => {
+                    let err = RowErr::Input(err);
+                    return Response::error(status(&err), err.code(), err);
+                }
+            };
+            match engine.process_transaction(tx) {
+                Ok(delta) => Response::ok(json!({ "delta": delta })),
+                Err(err) => {
+                    let err = RowErr::Rejected(err);
+                    Response::error(status(&err), err.code(), err)
+                }
+            }
+        }
+        ("GET", ["accounts"]) => {
+            let accounts: Vec<_> = engine
+                .snapshot()
+                .accounts
+                .iter()
+                .map(|account| AccountSnapshot::new(account, &amounts))
+                .collect();
+            Response::ok(accounts)
+        }
+        ("GET", ["accounts", client]) => {
+            let Ok(client) = client.parse::<u16>() else {
+                return Response::error(400, "malformed", format!("invalid client {client}"));
+            };
+            match engine.account(client) {
+                Some(account) => Response::ok(AccountSnapshot::new(&account, &amounts)),
+                None => Response::error(
+                    404,
+                    "unknown_client",
+                    format!("no account for client {client}"),
+                ),
+            }
+        }
+        ("GET", ["transactions", tx]) => {
+            let Ok(tx) = tx.parse::<u32>() else {
+                return Response::error(400, "malformed", format!("invalid transaction {tx}"));
+            };
+            match (engine.tx_owner(tx), engine.dispute_state(tx)) {
+                (Some(client), Some(state)) => Response::ok(json!({
+                    "tx": tx,
+                    "client": client,
+                    "state": state,
+                })),
+                _ => Response::error(404, "unknown_transaction", format!("no transaction {tx}")),
+            }
+        }
+        (_, ["transactions"] | ["accounts"] | ["accounts", _] | ["transactions", _]) => {
+            Response::error(405, "method_not_allowed", format!("{method} {path}"))
+        }
+        _ => Response::error(404, "not_found", format!("no resource {path}")),
+    }
+}
+
+/// A request read from a connection.
+struct Request {
+    method: String,
+    target: String,
+    body: String,
+}
+
+/// Read a request, or the response to a malformed request. `None` if the
+/// connection was closed before a request.
+///
+/// The request line, the headers and the body are bounded: a longer request
+/// line is answered with 400, too large or too many headers with 431 and a
+/// larger body with 413. A body shorter than its content length is answered
+/// with 400. Only bodies of a content length are read, a request with a
+/// `Transfer-Encoding` is answered with 501.
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Option<Result<Request, Response>>> {
+    let mut line = Vec::new();
+    match read_bounded_line(reader, &mut line, MAX_REQUEST_LINE) {
+        Ok(0) => return Ok(None),
+        Ok(_) => {}
+        Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+            return Ok(Some(Err(Response::error(
+                400,
+                "malformed",
+                format!("request line longer than {MAX_REQUEST_LINE} bytes"),
+            ))));
+        }
+        Err(err) => return Err(err),
+    }
+    let mut parts = std::str::from_utf8(&line)
+        .unwrap_or_default()
+        .split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Ok(Some(Err(Response::error(
+            400,
+            "malformed",
+            "invalid request line",
+        ))));
+    };
+    let (method, target) = (method.to_string(), target.to_string());
+
+    let headers_too_large = || {
+        Response::error(
+            431,
+            "headers_too_large",
+            format!("headers exceed {MAX_HEADER_SIZE} bytes"),
+        )
+    };
+    let mut length = 0;
+    let mut headers = 0;
+    let mut remaining = MAX_HEADER_SIZE;
+    loop {
+        // Leave room for the empty line ending the headers.
+        let read = match read_bounded_line(reader, &mut line, remaining + 2) {
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                return Ok(Some(Err(headers_too_large())));
+            }
+            Err(err) => return Err(err),
+        };
+
+        let header = String::from_utf8_lossy(&line);
+        if read == 0 || header.trim().is_empty() {
+            break;
+        }
+        if read as u64 > remaining {
+            return Ok(Some(Err(headers_too_large())));
+        }
+        remaining -= read as u64;
+        headers += 1;
+        if headers > MAX_HEADERS {
+            return Ok(Some(Err(Response::error(
+                431,
+                "headers_too_large",
+                format!("more than {MAX_HEADERS} headers"),
+            ))));
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                let Ok(value) = value.trim().parse() else {
+                    return Ok(Some(Err(Response::error(
+                        400,
+                        "malformed",
+                        "invalid content length",
+                    ))));
+                };
+                length = value;
+            } else if name.trim().eq_ignore_ascii_case("transfer-encoding") {
+                return Ok(Some(Err(Response::error(
+                    501,
+                    "not_implemented",
+                    "transfer encodings are not supported",
+                ))));
+            }
+        }
+    }
+
+    if length > MAX_BODY {
+        return Ok(Some(Err(Response::error(
+            413,
+            "body_too_large",
+            format!("body exceeds {MAX_BODY} bytes"),
+        ))));
+    }
+    let mut body = vec![0; length];
+    match reader.read_exact(&mut body) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+            return Ok(Some(Err(Response::error(
+                400,
+                "malformed",
+                format!("body shorter than its content length {length}"),
+            ))));
+        }
+        Err(err) => return Err(err),
+    }
+    let Ok(body) = String::from_utf8(body) else {
+        return Ok(Some(Err(Response::error(
+            400,
+            "malformed",
+            "body is not UTF-8",
+        ))));
+    };
+
+    Ok(Some(Ok(Request {
+        method,
+        target,
+        body,
+    })))
+}
+
+/// Answer a single request read from a connection.
+pub fn handle_connection<R: Read, W: Write>(
+    engine: &SharedEngine,
+    reader: R,
+    writer: W,
+) -> io::Result<()> {
+    let Some(request) = read_request(&mut BufReader::new(reader))? else {
+        return Ok(());
+    };
+
+    let response = match request {
+        Ok(request) => {
+            let response = route(engine, &request.method, &request.target, &request.body);
+            log::debug!(
+                "{} {} -> {}",
+                request.method,
+                request.target,
+                response.status
+            );
+            response
+        }
+        Err(response) => response,
+    };
+    response.write(BufWriter::new(writer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::ClientConfig,
+        server::{serve_tcp, Protocol},
+    };
+    use std::{
+        net::{Shutdown, TcpListener, TcpStream},
+        thread,
+    };
+
+    #[test]
+    fn test_route() {
+        let engine = SharedEngine::new(ClientConfig::default());
+        let post = |body| route(&engine, "POST", "/transactions", body);
+
+        let response = post(r#"{"Deposit":{"client":1,"tx":1,"amount":"2.5"}}"#);
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body["delta"]["available"], "2.5");
+
+        let response = post(r#"{"type":"withdrawal","client":1,"tx":2,"amount":"5"}"#);
+        assert_eq!(response.status, 422);
+        assert_eq!(response.body["code"], "insufficient_funds");
+        assert_eq!(
+            post(r#"{"type":"deposit","client":2,"tx":1,"amount":"1"}"#).status,
+            409
+        );
+        assert_eq!(post(r#"{"type":"dispute","client":1,"tx":9}"#).status, 404);
+        assert_eq!(post("not json").status, 400);
+
+        assert_eq!(post(r#"{"type":"dispute","client":1,"tx":1}"#).status, 200);
+        let response = route(&engine, "GET", "/transactions/1", "");
+        assert_eq!(
+            response.body,
+            json!({ "tx": 1, "client": 1, "state": "Disputed" })
+        );
+
+        let response = route(&engine, "GET", "/accounts/1", "");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body["held"], "2.5000");
+        assert_eq!(route(&engine, "GET", "/accounts", "").body[0]["client"], 1);
+
+        assert_eq!(route(&engine, "GET", "/accounts/3", "").status, 404);
+        assert_eq!(route(&engine, "GET", "/accounts/x", "").status, 400);
+        assert_eq!(route(&engine, "DELETE", "/accounts/1", "").status, 405);
+        assert_eq!(route(&engine, "GET", "/balances", "").status, 404);
+    }
+
+    #[test]
+    fn test_read_request_limits() {
+        let status = |request: &str| match read_request(&mut request.as_bytes()).unwrap() {
+            Some(Ok(_)) => 200,
+            Some(Err(response)) => response.status,
+            None => 0,
+        };
+
+        assert_eq!(status(""), 0);
+        assert_eq!(status("GET /accounts HTTP/1.1\r\nHost: a\r\n\r\n"), 200);
+
+        let long = "a".repeat(MAX_REQUEST_LINE as usize);
+        assert_eq!(status(&format!("GET /{long} HTTP/1.1\r\n\r\n")), 400);
+
+        let header = format!("X-Long: {}\r\n", "a".repeat(MAX_HEADER_SIZE as usize));
+        assert_eq!(status(&format!("GET / HTTP/1.1\r\n{header}\r\n")), 431);
+        // Headers of exactly the limit are accepted.
+        let value = "a".repeat(MAX_HEADER_SIZE as usize - "X-Long: \r\n".len());
+        assert_eq!(
+            status(&format!("GET / HTTP/1.1\r\nX-Long: {value}\r\n\r\n")),
+            200
+        );
+        assert_eq!(
+            status(&format!("GET / HTTP/1.1\r\nX-Long: {value}a\r\n\r\n")),
+            431
+        );
+        let headers = "X-Short: a\r\n".repeat(MAX_HEADERS + 1);
+        assert_eq!(status(&format!("GET / HTTP/1.1\r\n{headers}\r\n")), 431);
+        let headers = "X-Short: a\r\n".repeat(MAX_HEADERS);
+        assert_eq!(status(&format!("GET / HTTP/1.1\r\n{headers}\r\n")), 200);
+
+        let request =
+            "POST /transactions HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\n{}\r\n0\r\n\r\n";
+        assert_eq!(status(request), 501);
+
+        // The connection closes before the announced body is complete.
+        let request = "POST /transactions HTTP/1.1\r\nContent-Length: 10\r\n\r\n{}";
+        assert_eq!(status(request), 400);
+        let response = read_request(&mut request.as_bytes()).unwrap().unwrap();
+        assert_eq!(
+            response.err().unwrap().body["reason"],
+            "body shorter than its content length 10"
+        );
+    }
+
+    #[test]
+    fn test_serve_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let engine = SharedEngine::new(ClientConfig::default());
+        thread::spawn(move || serve_tcp(listener, engine, Protocol::Http));
+
+        let request = |request: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        let body = r#"{"type":"deposit","client":1,"tx":1,"amount":"1.0"}"#;
+        let response = request(&format!(
+            "POST /transactions HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        ));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+
+        let response = request("GET /accounts/1 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("Content-Type: application/json"));
+        assert_eq!(
+            serde_json::from_str::<Value>(body).unwrap(),
+            json!({
+                "client": 1,
+                "available": "1.0000",
+                "held": "0.0000",
+                "total": "1.0000",
+                "locked": false,
+            })
+        );
+    }
+}
@@ -0,0 +1,420 @@
+use crate::{
+    error::EngineErr,
+    types::{CsvTransaction, JsonTransaction, TransactionType},
+};
+
+use std::{
+    cell::RefCell,
+    fmt,
+    io::{self, BufRead, BufReader, Read},
+    rc::Rc,
+    str::FromStr,
+};
+
+/// Format of the transaction input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InputFormat {
+    /// CSV with a `type, client, tx, amount` header.
+    #[default]
+    Csv,
+    /// One JSON object per line.
+    JsonLines,
+}
+
+impl InputFormat {
+    /// Guess the format from the extension of the given path, defaulting to CSV.
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".jsonl") || path.ends_with(".ndjson") {
+            Self::JsonLines
+        } else {
+            Self::Csv
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "jsonl" => Ok(Self::JsonLines),
+            _ => Err("Unknown input format"),
+        }
+    }
+}
+
+/// Reasons a row of the input does not yield a transaction.
+#[derive(Debug)]
+pub enum InputErr {
+    /// The row could not be read or deserialized.
+    Malformed(String),
+    /// The row was read but does not describe a valid transaction.
+    Invalid(EngineErr),
+}
+
+impl InputErr {
+    /// A stable, machine-readable code of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Malformed(_) => "malformed",
+            Self::Invalid(err) => err.code(),
+        }
+    }
+}
+
+impl fmt::Display for InputErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed(err) => write!(f, "malformed row: {err}"),
+            Self::Invalid(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for InputErr {}
+
+/// A row of the input.
+#[derive(Debug)]
+pub struct InputRow {
+    /// Line number of the row in the input.
+    pub line: u64,
+    /// Raw content of the row.
+    pub raw: String,
+    /// The transaction described by the row.
+    pub transaction: Result<TransactionType, InputErr>,
+}
+
+/// The rows of an input, in order.
+pub type Rows<'a> = Box<dyn Iterator<Item = InputRow> + 'a>;
+
+/// Read the rows of the input in the given format.
+pub fn read_rows<'a, R: Read + 'a>(
+    reader: R,
+    format: InputFormat,
+) -> Result<Rows<'a>, Box<dyn std::error::Error>> {
+    match format {
+        InputFormat::Csv => Ok(Box::new(csv_rows(reader)?)),
+        InputFormat::JsonLines => Ok(Box::new(jsonl_rows(reader))),
+    }
+}
+
+/// Parse a single row in the given format. A CSV row has no header, its columns
+/// are `type, client, tx, amount`.
+pub fn parse_row(raw: &str, format: InputFormat) -> Result<TransactionType, InputErr> {
+    match format {
+        InputFormat::Csv => parse_csv(raw),
+        InputFormat::JsonLines => parse_json(raw),
+    }
+}
+
+fn parse_csv(raw: &str) -> Result<TransactionType, InputErr> {
+    let headers = csv::StringRecord::from(vec!["type", "client", "tx", "amount"]);
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .has_headers(false)
+        .from_reader(raw.as_bytes());
+
+    let record = reader
+        .records()
+        .next()
+        .unwrap_or_else(|| Ok(csv::StringRecord::new()))
+        .map_err(|err| InputErr::Malformed(err.to_string()))?;
+    let record = record
+        .deserialize::<CsvTransaction>(Some(&headers))
+        .map_err(|err| InputErr::Malformed(err.to_string()))?;
+    TransactionType::try_from(record).map_err(InputErr::Invalid)
+}
+
+/// Parse a JSON row, either in the flat CSV shape or in the shape of a serialized
+/// [`TransactionType`]. The error of the flat shape is reported if neither matches.
+fn parse_json(raw: &str) -> Result<TransactionType, InputErr> {
+    let record = match serde_json::from_str::<JsonTransaction>(raw) {
+        Ok(record) => CsvTransaction::from(record),
+        Err(err) => match serde_json::from_str::<TransactionType>(raw) {
+            Ok(tx) => CsvTransaction::from(tx),
+            Err(_) => return Err(InputErr::Malformed(err.to_string())),
+        },
+    };
+    TransactionType::try_from(record).map_err(InputErr::Invalid)
+}
+
+/// The bytes read from an input that no row has claimed yet, so the raw content
+/// of a row can be taken from its byte range.
+#[derive(Default)]
+struct Recorded {
+    /// Offset of the first recorded byte in the input.
+    start: u64,
+    bytes: Vec<u8>,
+}
+
+impl Recorded {
+    /// The content between the given offsets, without line terminators. The bytes
+    /// up to the end are dropped.
+    fn take(&mut self, start: u64, end: u64) -> String {
+        let to = (end.saturating_sub(self.start) as usize).min(self.bytes.len());
+        let from = (start.saturating_sub(self.start) as usize).min(to);
+        let raw = String::from_utf8_lossy(&self.bytes[from..to])
+            .trim_matches(['\r', '\n'])
+            .to_string();
+        self.bytes.drain(..to);
+        self.start += to as u64;
+        raw
+    }
+}
+
+/// Records the bytes read from the inner reader.
+struct Recorder<R> {
+    reader: R,
+    recorded: Rc<RefCell<Recorded>>,
+}
+
+impl<R: Read> Read for Recorder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.recorded
+            .borrow_mut()
+            .bytes
+            .extend_from_slice(&buf[..read]);
+        Ok(read)
+    }
+}
+
+fn csv_rows<R: Read>(reader: R) -> Result<impl Iterator<Item = InputRow>, csv::Error> {
+    let recorded = Rc::new(RefCell::new(Recorded::default()));
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .has_headers(true)
+        .from_reader(Recorder {
+            reader,
+            recorded: Rc::clone(&recorded),
+        });
+    let headers = reader.headers()?.clone();
+    let mut record = csv::StringRecord::new();
+
+    // After a read error the reader reports the end of the input.
+    Ok(std::iter::from_fn(move || {
+        let start = reader.position().byte();
+        let read = reader.read_record(&mut record);
+        let raw = recorded.borrow_mut().take(start, reader.position().byte());
+        match read {
+            Ok(false) => None,
+            Err(err) => {
+                let line = err.position().map_or(0, |pos| pos.line());
+                Some(InputRow {
+                    line,
+                    raw,
+                    transaction: Err(InputErr::Malformed(err.to_string())),
+                })
+            }
+            Ok(true) => {
+                let line = record.position().map_or(0, |pos| pos.line());
+                let transaction = record
+                    .deserialize::<CsvTransaction>(Some(&headers))
+                    .map_err(|err| InputErr::Malformed(err.to_string()))
+                    .and_then(|record| {
+                        TransactionType::try_from(record).map_err(InputErr::Invalid)
+                    });
+                Some(InputRow {
+                    line,
+                    raw,
+                    transaction,
+                })
+            }
+        }
+    }))
+}
+
+fn jsonl_rows<R: Read>(reader: R) -> impl Iterator<Item = InputRow> {
+    let mut failed = false;
+
+    BufReader::new(reader)
+        .lines()
+        .zip(1..)
+        .map_while(move |(line, number)| {
+            // Stop after the first read error, the reader cannot recover from it.
+            if failed {
+                return None;
+            }
+
+            let raw = match line {
+                Ok(raw) => raw,
+                Err(err) => {
+                    failed = true;
+                    return Some(Some(InputRow {
+                        line: number,
+                        raw: String::new(),
+                        transaction: Err(InputErr::Malformed(err.to_string())),
+                    }));
+                }
+            };
+            if raw.trim().is_empty() {
+                return Some(None);
+            }
+
+            let transaction = parse_json(&raw);
+
+            Some(Some(InputRow {
+                line: number,
+                raw,
+                transaction,
+            }))
+        })
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{EngineErrKind, ParseErr};
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn test_jsonl_rows() {
+        let input = r#"{"type":"deposit","client":1,"tx":1,"amount":"1.5"}
+{"Withdrawal":{"client":1,"tx":2,"amount":"0.5"}}
+
+{"type":"deposit","client":1,"tx":3,"amount":"1.00001"}
+not json
+"#;
+
+        let rows: Vec<_> = read_rows(input.as_bytes(), InputFormat::JsonLines)
+            .unwrap()
+            .collect();
+        assert_eq!(rows.len(), 4);
+
+        assert_eq!(rows[0].line, 1);
+        assert!(matches!(
+            rows[0].transaction,
+            Ok(TransactionType::Deposit {
+                client: 1,
+                tx: 1,
+                ..
+            })
+        ));
+        assert!(matches!(
+            rows[1].transaction,
+            Ok(TransactionType::Withdrawal {
+                client: 1,
+                tx: 2,
+                ..
+            })
+        ));
+
+        // Validated like CSV rows, blank lines are skipped but counted.
+        assert_eq!(rows[2].line, 4);
+        let Err(InputErr::Invalid(err)) = &rows[2].transaction else {
+            panic!("expected invalid row");
+        };
+        assert!(matches!(
+            err.kind(),
+            EngineErrKind::Parse(ParseErr::InvalidPrecision)
+        ));
+
+        assert_eq!(rows[3].line, 5);
+        assert_eq!(rows[3].raw, "not json");
+        assert!(matches!(rows[3].transaction, Err(InputErr::Malformed(_))));
+    }
+
+    #[test]
+    fn test_json_amounts() {
+        let amount = |raw| parse_json(raw).map(|tx| tx.amount());
+        assert_eq!(
+            amount(r#"{"type":"deposit","client":1,"tx":1,"amount":1.5}"#).unwrap(),
+            Some(Decimal::new(15, 1))
+        );
+        assert_eq!(
+            amount(r#"{"type":"deposit","client":1,"tx":1,"amount":2}"#).unwrap(),
+            Some(Decimal::from(2))
+        );
+        // Numbers are kept as written.
+        assert_eq!(
+            amount(r#"{"type":"deposit","client":1,"tx":1,"amount":12345678901234.0001}"#).unwrap(),
+            Some(Decimal::new(123456789012340001, 4))
+        );
+        assert_eq!(
+            amount(r#"{"type":"dispute","client":1,"tx":1,"amount":null}"#).unwrap(),
+            None
+        );
+
+        // The error of the flat shape is reported.
+        let Err(InputErr::Malformed(err)) =
+            amount(r#"{"type":"deposit","client":1,"tx":1,"amount":true}"#)
+        else {
+            panic!("expected a malformed row");
+        };
+        assert!(err.contains("expected a string or a number"), "{err}");
+        let Err(InputErr::Malformed(err)) = amount(r#"{"type":"deposit","tx":1}"#) else {
+            panic!("expected a malformed row");
+        };
+        assert!(err.contains("missing field `client`"), "{err}");
+    }
+
+    #[test]
+    fn test_parse_row() {
+        assert!(matches!(
+            parse_row("deposit, 1, 2, 1.5", InputFormat::Csv),
+            Ok(TransactionType::Deposit {
+                client: 1,
+                tx: 2,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_row("dispute,1,2", InputFormat::Csv),
+            Ok(TransactionType::Dispute { client: 1, tx: 2 })
+        ));
+        assert!(matches!(
+            parse_row("deposit,1", InputFormat::Csv),
+            Err(InputErr::Malformed(_))
+        ));
+        assert!(matches!(
+            parse_row(
+                r#"{"type":"withdrawal","client":1,"tx":3}"#,
+                InputFormat::JsonLines
+            ),
+            Err(InputErr::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_csv_rows() {
+        let input = "type, client, tx, amount\ndeposit, 1, 1, 1.0\nfewfew,\ndeposi, 1, 2, 1.0\n";
+
+        let rows: Vec<_> = read_rows(input.as_bytes(), InputFormat::Csv)
+            .unwrap()
+            .collect();
+        assert_eq!(rows.len(), 3);
+
+        assert_eq!(rows[0].line, 2);
+        assert_eq!(rows[0].raw, "deposit, 1, 1, 1.0");
+        assert!(rows[0].transaction.is_ok());
+        assert_eq!(rows[1].raw, "fewfew,");
+        assert!(matches!(rows[1].transaction, Err(InputErr::Malformed(_))));
+        assert_eq!(rows[2].line, 4);
+        assert!(matches!(rows[2].transaction, Err(InputErr::Invalid(_))));
+    }
+
+    #[test]
+    fn test_csv_rows_raw() {
+        let mut input = b"type,client,tx,amount\r\n\r\n\"deposit\", 1,1,1.0\r\n".to_vec();
+        input.extend_from_slice(b"deposit,\xff,2,1.0\r\nwithdrawal,1,3,0.5");
+
+        let rows: Vec<_> = read_rows(input.as_slice(), InputFormat::Csv)
+            .unwrap()
+            .collect();
+        assert_eq!(rows.len(), 3);
+
+        // The raw content is the row as read, quotes and whitespace included.
+        assert_eq!(rows[0].raw, "\"deposit\", 1,1,1.0");
+        assert!(rows[0].transaction.is_ok());
+
+        // Rows that cannot be read keep their content.
+        assert_eq!(rows[1].raw, "deposit,\u{fffd},2,1.0");
+        assert!(matches!(rows[1].transaction, Err(InputErr::Malformed(_))));
+
+        assert_eq!(rows[2].raw, "withdrawal,1,3,0.5");
+    }
+}
@@ -0,0 +1,437 @@
+use crate::{
+    books::TrialBalance,
+    client::{ClientAccount, DisputeState},
+    ledger::{Balances, EventKind},
+    types::TransactionType,
+};
+
+use rust_decimal::Decimal;
+use std::{collections::HashMap, fmt, str::FromStr};
+
+/// Whether the invariants of an account are checked after every transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvariantCheck {
+    /// Invariants are not checked.
+    Off,
+    /// Violations are logged as errors.
+    Report,
+    /// Transactions violating an invariant are rejected with
+    /// [`ClientErr::InvariantViolated`](crate::client::ClientErr::InvariantViolated).
+    Abort,
+}
+
+impl Default for InvariantCheck {
+    /// Reported in debug builds, opt-in in release builds.
+    fn default() -> Self {
+        if cfg!(debug_assertions) {
+            Self::Report
+        } else {
+            Self::Off
+        }
+    }
+}
+
+impl FromStr for InvariantCheck {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "report" => Ok(Self::Report),
+            "abort" => Ok(Self::Abort),
+            _ => Err("Unknown invariant check"),
+        }
+    }
+}
+
+/// A broken invariant of a client account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// The total funds are not the sum of the available and held funds.
+    TotalMismatch {
+        client: u16,
+        available: Decimal,
+        held: Decimal,
+        total: Decimal,
+    },
+    /// The held funds are negative.
+    NegativeHeld { client: u16, held: Decimal },
+    /// The ledger of a locked account has events after the chargeback locking it.
+    LockedChanged { client: u16 },
+    /// The held funds differ from the amounts of the disputed transactions.
+    HeldMismatch {
+        client: u16,
+        held: Decimal,
+        disputed: Decimal,
+    },
+    /// The account is locked without a chargeback, or not locked after one.
+    LockMismatch { client: u16, locked: bool },
+    /// The balances differ from the sum of the ledger events.
+    LedgerMismatch { client: u16 },
+    /// A processed transaction has no dispute state.
+    MissingDisputeState { client: u16, tx: u32 },
+    /// A dispute state belongs to no processed transaction.
+    OrphanedDisputeState { client: u16, tx: u32 },
+    /// A transaction ID is used by several clients.
+    DuplicateTransaction { tx: u32, clients: [u16; 2] },
+    /// The books beneath the accounts do not sum to zero.
+    Unbalanced { sum: Decimal },
+}
+
+impl Violation {
+    /// A stable, machine-readable code of the violation.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::TotalMismatch { .. } => "total_mismatch",
+            Self::NegativeHeld { .. } => "negative_held",
+            Self::LockedChanged { .. } => "locked_changed",
+            Self::HeldMismatch { .. } => "held_mismatch",
+            Self::LockMismatch { .. } => "lock_mismatch",
+            Self::LedgerMismatch { .. } => "ledger_mismatch",
+            Self::MissingDisputeState { .. } => "missing_dispute_state",
+            Self::OrphanedDisputeState { .. } => "orphaned_dispute_state",
+            Self::DuplicateTransaction { .. } => "duplicate_transaction",
+            Self::Unbalanced { .. } => "unbalanced",
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TotalMismatch {
+                client,
+                available,
+                held,
+                total,
+            } => write!(
+                f,
+                "[client {client}] total {total} is not available {available} + held {held}"
+            ),
+            Self::NegativeHeld { client, held } => {
+                write!(f, "[client {client}] negative held funds {held}")
+            }
+            Self::LockedChanged { client } => {
+                write!(f, "[client {client}] changed after it was locked")
+            }
+            Self::HeldMismatch {
+                client,
+                held,
+                disputed,
+            } => write!(
+                f,
+                "[client {client}] held funds {held} differ from the disputed amounts {disputed}"
+            ),
+            Self::LockMismatch {
+                client,
+                locked: true,
+            } => {
+                write!(f, "[client {client}] locked without a chargeback")
+            }
+            Self::LockMismatch {
+                client,
+                locked: false,
+            } => write!(f, "[client {client}] not locked after a chargeback"),
+            Self::LedgerMismatch { client } => {
+                write!(f, "[client {client}] balances differ from the ledger")
+            }
+            Self::MissingDisputeState { client, tx } => {
+                write!(f, "[client {client}] transaction {tx} has no dispute state")
+            }
+            Self::OrphanedDisputeState { client, tx } => {
+                write!(
+                    f,
+                    "[client {client}] dispute state of unknown transaction {tx}"
+                )
+            }
+            Self::DuplicateTransaction { tx, clients } => write!(
+                f,
+                "transaction {tx} used by clients {} and {}",
+                clients[0], clients[1]
+            ),
+            Self::Unbalanced { sum } => write!(f, "the books sum to {sum}"),
+        }
+    }
+}
+
+/// Check the invariants holding after every transaction, given the balances before it.
+///
+/// The total is recomputed from the available and held funds by every
+/// transaction, so it is only checked by [`check_account`].
+pub fn check_transaction(before: &Balances, account: &ClientAccount) -> Vec<Violation> {
+    let client = account.client();
+    let mut violations: Vec<_> = check_held(account).into_iter().collect();
+
+    // Only a chargeback locks an account. Locked accounts reject every
+    // transaction, so the lock never changes otherwise.
+    let chargeback = account
+        .events()
+        .last()
+        .is_some_and(|event| event.kind == EventKind::Chargeback);
+    if account.is_locked() != (before.locked || chargeback) {
+        violations.push(Violation::LockMismatch {
+            client,
+            locked: account.is_locked(),
+        });
+    }
+
+    violations
+}
+
+/// Check that the held funds are not negative.
+fn check_held(account: &ClientAccount) -> Option<Violation> {
+    (account.held() < Decimal::ZERO).then(|| Violation::NegativeHeld {
+        client: account.client(),
+        held: account.held(),
+    })
+}
+
+/// Check all invariants of an account, including its transaction history and ledger.
+pub fn check_account(account: &ClientAccount) -> Vec<Violation> {
+    let client = account.client();
+    let mut violations = Vec::new();
+
+    if account.total() != account.available() + account.held() {
+        violations.push(Violation::TotalMismatch {
+            client,
+            available: account.available(),
+            held: account.held(),
+            total: account.total(),
+        });
+    }
+    violations.extend(check_held(account));
+
+    for tx in account.missing_dispute_states() {
+        violations.push(Violation::MissingDisputeState { client, tx });
+    }
+    for tx in account.orphaned_dispute_states() {
+        violations.push(Violation::OrphanedDisputeState { client, tx });
+    }
+
+    let mut disputed = Decimal::ZERO;
+    let mut charged_back = false;
+    for (tx, state) in account.transactions() {
+        match (tx, state) {
+            (
+                TransactionType::Deposit { amount, .. }
+                | TransactionType::Withdrawal { amount, .. },
+                DisputeState::Disputed,
+            ) => disputed += amount,
+            (_, DisputeState::ChargedBack) => charged_back = true,
+            _ => {}
+        }
+    }
+    if account.held() != disputed {
+        violations.push(Violation::HeldMismatch {
+            client,
+            held: account.held(),
+            disputed,
+        });
+    }
+    if account.is_locked() != charged_back {
+        violations.push(Violation::LockMismatch {
+            client,
+            locked: account.is_locked(),
+        });
+    }
+
+    let locking = account.events().iter().position(|event| event.delta.locked);
+    if locking.is_some_and(|seq| seq + 1 < account.events().len()) {
+        violations.push(Violation::LockedChanged { client });
+    }
+
+    let ledger = account.balances_at(u64::MAX);
+    if ledger.available != account.available()
+        || ledger.held != account.held()
+        || ledger.total != account.total()
+        || ledger.locked != account.is_locked()
+    {
+        violations.push(Violation::LedgerMismatch { client });
+    }
+
+    violations
+}
+
+/// Check all invariants of the accounts and of the books beneath them.
+pub fn audit<'a>(accounts: impl IntoIterator<Item = &'a ClientAccount>) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let mut owners: HashMap<u32, u16> = HashMap::new();
+    let mut trial = TrialBalance::new();
+
+    for account in accounts {
+        violations.extend(check_account(account));
+        trial.add_account(account);
+
+        for (tx, _) in account.transactions() {
+            let tx = tx.transaction_id();
+            if let Some(owner) = owners.insert(tx, account.client()) {
+                violations.push(Violation::DuplicateTransaction {
+                    tx,
+                    clients: [owner, account.client()],
+                });
+            }
+        }
+    }
+
+    if !trial.sum().is_zero() {
+        violations.push(Violation::Unbalanced { sum: trial.sum() });
+    }
+
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientConfig;
+
+    fn account() -> ClientAccount {
+        let mut account = ClientAccount::new(1);
+        for tx in [
+            TransactionType::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Decimal::new(30, 1),
+            },
+            TransactionType::Deposit {
+                client: 1,
+                tx: 2,
+                amount: Decimal::new(10, 1),
+            },
+            TransactionType::Dispute { client: 1, tx: 2 },
+        ] {
+            account.process_transaction(tx).unwrap();
+        }
+        account
+    }
+
+    #[test]
+    fn test_audit() {
+        let account = account();
+        assert!(audit([&account]).is_empty());
+
+        let mut broken = account.clone();
+        broken.held = Decimal::new(-10, 1);
+        let codes: Vec<_> = audit([&broken]).iter().map(Violation::code).collect();
+        assert_eq!(
+            codes,
+            [
+                "total_mismatch",
+                "negative_held",
+                "held_mismatch",
+                "ledger_mismatch"
+            ]
+        );
+
+        let mut other = ClientAccount::new(2);
+        other
+            .process_transaction(TransactionType::Deposit {
+                client: 2,
+                tx: 1,
+                amount: Decimal::new(10, 1),
+            })
+            .unwrap();
+        assert_eq!(
+            audit([&account, &other]),
+            [Violation::DuplicateTransaction {
+                tx: 1,
+                clients: [1, 2]
+            }]
+        );
+    }
+
+    #[test]
+    fn test_check_transaction() {
+        let mut account = account();
+        let before = account.balances_at(u64::MAX);
+        assert!(check_transaction(&before, &account).is_empty());
+
+        // Locked by a dispute.
+        account.held = -Decimal::ONE;
+        account.locked = true;
+        let codes: Vec<_> = check_transaction(&before, &account)
+            .iter()
+            .map(Violation::code)
+            .collect();
+        assert_eq!(codes, ["negative_held", "lock_mismatch"]);
+
+        // Locked by a chargeback.
+        let mut account = self::account();
+        let before = account.balances_at(u64::MAX);
+        account
+            .process_transaction(TransactionType::Chargeback { client: 1, tx: 2 })
+            .unwrap();
+        assert!(check_transaction(&before, &account).is_empty());
+        account.locked = false;
+        assert_eq!(
+            check_transaction(&before, &account),
+            [Violation::LockMismatch {
+                client: 1,
+                locked: false
+            }]
+        );
+    }
+
+    #[test]
+    fn test_check_account_history() {
+        let mut account = account();
+        account
+            .process_transaction(TransactionType::Chargeback { client: 1, tx: 2 })
+            .unwrap();
+        assert!(check_account(&account).is_empty());
+
+        // Edit the stored account, as `tx audit` reads it.
+        let edit = |edit: fn(&mut serde_json::Value)| {
+            let mut stored = serde_json::to_value(&account).unwrap();
+            edit(&mut stored);
+            let account: ClientAccount = serde_json::from_value(stored).unwrap();
+            check_account(&account)
+        };
+
+        let violations = edit(|stored| {
+            stored["dispute_state"].as_object_mut().unwrap().remove("1");
+        });
+        assert_eq!(
+            violations,
+            [Violation::MissingDisputeState { client: 1, tx: 1 }]
+        );
+
+        let violations = edit(|stored| {
+            stored["processed_tx"].as_object_mut().unwrap().remove("2");
+        });
+        let codes: Vec<_> = violations.iter().map(Violation::code).collect();
+        assert_eq!(codes, ["orphaned_dispute_state", "lock_mismatch"]);
+
+        // An event after the chargeback locking the account.
+        let violations = edit(|stored| {
+            let events = stored["events"].as_array_mut().unwrap();
+            let mut deposit = events[0].clone();
+            deposit["seq"] = 5.into();
+            events.push(deposit);
+        });
+        let codes: Vec<_> = violations.iter().map(Violation::code).collect();
+        assert_eq!(codes, ["locked_changed", "ledger_mismatch"]);
+    }
+
+    #[test]
+    fn test_abort() {
+        let config = ClientConfig {
+            invariants: InvariantCheck::Abort,
+            ..Default::default()
+        };
+        let mut account = ClientAccount::with_config(1, config);
+        // Corrupt the account behind the back of the books.
+        account.held = -Decimal::ONE;
+        let err = account
+            .process_transaction(TransactionType::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Decimal::ONE,
+            })
+            .unwrap_err();
+        assert_eq!(err.code(), "invariant_violated");
+        // The rejected transaction is not applied.
+        assert!(account.transactions().next().is_none());
+        assert_eq!(account.available(), Decimal::ZERO);
+    }
+}
@@ -0,0 +1,282 @@
+use crate::{client::BalanceDelta, storage::StorageErr, types::TransactionType};
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    time::SystemTime,
+};
+
+/// An input of a run, identified by its path, size and modification time. Stdin
+/// cannot be identified.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputId {
+    /// The path of the input, `-` for stdin.
+    pub source: String,
+    /// Size of the input in bytes, `None` for stdin.
+    pub size: Option<u64>,
+    /// Last modification of the input, `None` for stdin.
+    pub modified: Option<SystemTime>,
+}
+
+impl InputId {
+    /// Identify the input at the given path, `-` for stdin.
+    pub fn of(source: &str) -> io::Result<Self> {
+        if source == "-" {
+            return Ok(Self {
+                source: source.to_string(),
+                size: None,
+                modified: None,
+            });
+        }
+
+        let metadata = std::fs::metadata(source)?;
+        Ok(Self {
+            source: source.to_string(),
+            size: Some(metadata.len()),
+            modified: Some(metadata.modified()?),
+        })
+    }
+
+    /// True if the input can be read again with the same content, i.e. it is not stdin.
+    pub fn is_identified(&self) -> bool {
+        self.size.is_some()
+    }
+}
+
+/// An accepted transaction recorded in the [`Journal`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// The input the row was read from, `-` for stdin.
+    pub source: String,
+    /// Line number of the row in the input.
+    pub line: u64,
+    /// The accepted transaction.
+    pub transaction: TransactionType,
+    /// The resulting change of the balances of the account.
+    pub delta: BalanceDelta,
+}
+
+/// A line of the [`Journal`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JournalRecord {
+    /// A run over the given inputs started.
+    Start { inputs: Vec<InputId> },
+    /// A row of the run was applied.
+    Applied(JournalEntry),
+    /// A row of the run was malformed or rejected. It has no effect on the state.
+    Rejected { source: String, line: u64 },
+    /// The run processed all its inputs.
+    Complete,
+}
+
+/// Append-only journal of the runs over the inputs, one JSON record per line.
+///
+/// Every row of a run is recorded after it was processed: the accepted
+/// transactions with the change of the balances they caused, which rebuild the
+/// state, and the rejected rows, so that an unfinished run can resume after the
+/// last recorded row.
+///
+/// Records are committed, i.e. flushed and synced to disk, every
+/// `commit_interval` records and on [`Journal::commit`]. A crash can leave a
+/// partially written last line, which is dropped by [`Journal::recover`].
+#[derive(Debug)]
+pub struct Journal {
+    writer: BufWriter<File>,
+    commit_interval: u64,
+    pending: u64,
+}
+
+impl Journal {
+    /// Read the complete records of the journal at the given path, truncating a
+    /// partially written last line. A missing journal has no records.
+    ///
+    /// Lines without a record kind, written before runs were recorded, are read as
+    /// applied entries.
+    pub fn recover(path: impl AsRef<Path>) -> Result<Vec<JournalRecord>, StorageErr> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut entries = Vec::new();
+        let mut committed = 0;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 {
+                break;
+            }
+            if !line.ends_with('\n') {
+                log::warn!("Dropping partial record at the end of {}", path.display());
+                break;
+            }
+
+            let record = serde_json::from_str(&line)
+                .or_else(|err| {
+                    serde_json::from_str(&line)
+                        .map(JournalRecord::Applied)
+                        .map_err(|_| err)
+                })
+                .map_err(|err| {
+                    StorageErr::Corrupt(format!("{}:{}: {err}", path.display(), entries.len() + 1))
+                })?;
+            entries.push(record);
+            committed += read as u64;
+        }
+
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(committed)?;
+        Ok(entries)
+    }
+
+    /// Open the journal at the given path for appending, committing every
+    /// `commit_interval` records.
+    pub fn open(path: impl AsRef<Path>, commit_interval: u64) -> Result<Self, StorageErr> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            commit_interval: commit_interval.max(1),
+            pending: 0,
+        })
+    }
+
+    /// Append a record, committing if the commit interval is reached.
+    pub fn append(&mut self, record: &JournalRecord) -> Result<(), StorageErr> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")?;
+
+        self.pending += 1;
+        if self.pending >= self.commit_interval {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    /// Flush the appended records and sync them to disk.
+    pub fn commit(&mut self) -> Result<(), StorageErr> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        self.pending = 0;
+        Ok(())
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        if let Err(err) = self.commit() {
+            log::error!("Failed to commit the journal: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+
+    fn entry(line: u64) -> JournalRecord {
+        JournalRecord::Applied(JournalEntry {
+            source: "a.csv".into(),
+            line,
+            transaction: TransactionType::Deposit {
+                client: 1,
+                tx: line as u32,
+                amount: Decimal::new(10, 1),
+            },
+            delta: BalanceDelta {
+                available: Decimal::new(10, 1),
+                total: Decimal::new(10, 1),
+                ..Default::default()
+            },
+        })
+    }
+
+    #[test]
+    fn test_recover_partial_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.jsonl");
+
+        let mut journal = Journal::open(&path, 10).unwrap();
+        journal.append(&entry(2)).unwrap();
+        journal.append(&entry(3)).unwrap();
+        drop(journal);
+
+        // A crash in the middle of writing an entry.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"kind":"applied","source":"a.csv","li"#)
+            .unwrap();
+        drop(file);
+
+        assert_eq!(Journal::recover(&path).unwrap(), [entry(2), entry(3)]);
+
+        // The partial entry is truncated before appending.
+        let mut journal = Journal::open(&path, 1).unwrap();
+        journal.append(&entry(4)).unwrap();
+        assert_eq!(
+            Journal::recover(&path).unwrap(),
+            [entry(2), entry(3), entry(4)]
+        );
+    }
+
+    #[test]
+    fn test_recover_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.jsonl");
+        let input = dir.path().join("a.csv");
+        std::fs::write(&input, "type,client,tx,amount\n").unwrap();
+
+        let records = [
+            JournalRecord::Start {
+                inputs: vec![
+                    InputId::of(input.to_str().unwrap()).unwrap(),
+                    InputId::of("-").unwrap(),
+                ],
+            },
+            entry(2),
+            JournalRecord::Rejected {
+                source: "a.csv".into(),
+                line: 3,
+            },
+            JournalRecord::Complete,
+        ];
+        let mut journal = Journal::open(&path, 1).unwrap();
+        for record in &records {
+            journal.append(record).unwrap();
+        }
+        drop(journal);
+
+        // A line written before runs were recorded is an applied entry.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        let JournalRecord::Applied(legacy) = entry(4) else {
+            unreachable!()
+        };
+        serde_json::to_writer(&mut file, &legacy).unwrap();
+        file.write_all(b"\n").unwrap();
+
+        let recovered = Journal::recover(&path).unwrap();
+        assert_eq!(recovered[..4], records);
+        assert_eq!(recovered[4], entry(4));
+
+        let JournalRecord::Start { inputs } = &recovered[0] else {
+            panic!("expected the start of a run");
+        };
+        assert_eq!(inputs[0].size, Some(22));
+        assert!(inputs[0].is_identified());
+        assert!(!inputs[1].is_identified());
+    }
+
+    #[test]
+    fn test_recover_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Journal::recover(dir.path().join("journal.jsonl"))
+            .unwrap()
+            .is_empty());
+    }
+}
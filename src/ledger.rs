@@ -0,0 +1,99 @@
+use crate::{books::Entry, client::BalanceDelta, output::AmountFormat, types::TransactionType};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+/// Kind of an operation applied to an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// Funds were deposited.
+    Deposit,
+    /// Funds were withdrawn.
+    Withdrawal,
+    /// Funds were held by a dispute.
+    Hold,
+    /// Held funds were released by a resolve.
+    Release,
+    /// Held funds were charged back and the account locked.
+    Chargeback,
+}
+
+impl EventKind {
+    /// The kind of event recorded for the transaction.
+    pub fn of(tx: &TransactionType) -> Self {
+        match tx {
+            TransactionType::Deposit { .. } => Self::Deposit,
+            TransactionType::Withdrawal { .. } => Self::Withdrawal,
+            TransactionType::Dispute { .. } => Self::Hold,
+            TransactionType::Resolve { .. } => Self::Release,
+            TransactionType::Chargeback { .. } => Self::Chargeback,
+        }
+    }
+}
+
+/// An operation applied to an account, in the order of the account's ledger.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerEvent {
+    /// Position of the event in the ledger of the account, starting at 1.
+    pub seq: u64,
+    pub kind: EventKind,
+    /// The transaction applied, or referenced by a dispute, resolve or chargeback.
+    pub tx: u32,
+    /// The change of the balances.
+    pub delta: BalanceDelta,
+    /// The balanced entries posted to the books.
+    pub entries: Vec<Entry>,
+}
+
+/// The balances of an account at a point of its ledger.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Balances {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl Balances {
+    /// Apply the change of an event.
+    pub fn apply(&mut self, delta: &BalanceDelta) {
+        self.available += delta.available;
+        self.held += delta.held;
+        self.total += delta.total;
+        self.locked |= delta.locked;
+    }
+}
+
+/// A line of the statement of a client: an event and the balances after it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StatementLine {
+    pub seq: u64,
+    pub kind: EventKind,
+    pub tx: u32,
+    pub available_change: Decimal,
+    pub held_change: Decimal,
+    pub total_change: Decimal,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl StatementLine {
+    /// Constructs a new [`StatementLine`], formatting the amounts.
+    pub fn new(event: &LedgerEvent, balances: &Balances, amounts: &AmountFormat) -> Self {
+        Self {
+            seq: event.seq,
+            kind: event.kind,
+            tx: event.tx,
+            available_change: amounts.apply(event.delta.available),
+            held_change: amounts.apply(event.delta.held),
+            total_change: amounts.apply(event.delta.total),
+            available: amounts.apply(balances.available),
+            held: amounts.apply(balances.held),
+            total: amounts.apply(balances.total),
+            locked: balances.locked,
+        }
+    }
+}
@@ -0,0 +1,36 @@
+//! A transaction engine processing deposits, withdrawals and disputes for
+//! client accounts.
+//!
+//! The entry point is the [`PaymentEngine`], which is fed [`TransactionType`]s
+//! and maintains a [`ClientAccount`] per client.
+
+pub mod books;
+pub mod client;
+pub mod engine;
+pub mod error;
+pub mod http;
+pub mod input;
+pub mod invariant;
+pub mod journal;
+pub mod ledger;
+pub mod output;
+pub mod rejection;
+pub mod server;
+pub mod shard;
+pub mod shared;
+pub mod snapshot;
+pub mod storage;
+pub mod stream;
+pub mod summary;
+pub mod types;
+
+pub use client::{BalanceDelta, ClientAccount, ClientConfig, ClientErr, DisputeState};
+pub use engine::{AccountOrder, PaymentEngine};
+pub use error::{EngineErr, EngineErrKind, ParseErr};
+pub use output::{AccountSnapshot, AmountFormat, OutputFormat, OutputOptions, Rounding};
+pub use shard::ShardedEngine;
+pub use shared::SharedEngine;
+pub use snapshot::EngineSnapshot;
+pub use storage::{FileStorage, MemoryStorage, Storage, StorageErr};
+pub use stream::{RowErr, RowOutcome};
+pub use types::TransactionType;
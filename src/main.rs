@@ -1,52 +1,1255 @@
-mod client;
-mod engine;
-mod types;
+mod cli;
 
-use crate::{
-    engine::PaymentEngine,
-    types::{CsvTransaction, TransactionType},
+use crate::cli::{
+    AuditArgs, Cli, Command, EngineArgs, InputArgs, InspectArgs, OutputArgs, ProcessArgs,
+    ReplayArgs, RunArgs, ServeArgs, StatementArgs, TrialBalanceArgs, ValidateArgs,
 };
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+use clap::Parser;
+use serde::Serialize;
+use std::{
+    error::Error,
+    fmt,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+    process::ExitCode,
+};
+use tx::{
+    input::{self, InputFormat, InputRow},
+    invariant,
+    journal::{InputId, Journal, JournalEntry, JournalRecord},
+    ledger::StatementLine,
+    output,
+    rejection::{Rejection, RejectionFormat, RejectionSink},
+    server,
+    summary::RunSummary,
+    AccountSnapshot, ClientErr, DisputeState, EngineErrKind, EngineSnapshot, FileStorage,
+    MemoryStorage, OutputFormat, PaymentEngine, RowErr, RowOutcome, ShardedEngine, SharedEngine,
+    Storage, StorageErr, TransactionType,
+};
+
+/// Some rows were rejected.
+const EXIT_REJECTED: u8 = 1;
+// Exit code 2 is used by clap for usage errors.
+/// The run was aborted by `--strict`.
+const EXIT_STRICT: u8 = 3;
+/// The inputs could not be read.
+const EXIT_INPUT: u8 = 4;
+/// The output could not be written.
+const EXIT_IO: u8 = 5;
+/// The state of the engine could not be loaded or stored.
+const EXIT_STORAGE: u8 = 6;
+/// The books do not balance or an invariant of the accounts is violated.
+const EXIT_AUDIT: u8 = 7;
+/// The requested client has no account.
+const EXIT_UNKNOWN_CLIENT: u8 = 8;
+
+/// The engine of the CLI, in memory or backed by `--storage`.
+type Engine = PaymentEngine<Box<dyn Storage>>;
+
+/// A failed run, mapped to an exit code.
+#[derive(Debug)]
+enum Failure {
+    /// The inputs could not be opened or read.
+    Input(Box<dyn Error>),
+    /// The output could not be written.
+    Output(Box<dyn Error>),
+    /// The run was aborted by `--strict`.
+    Strict(StrictAbort),
+    /// The state of the engine could not be loaded or stored.
+    Storage(Box<dyn Error>),
+    /// The books do not balance or an invariant of the accounts is violated.
+    Audit(String),
+    /// The requested client has no account.
+    UnknownClient(u16),
+}
+
+impl Failure {
+    fn input(err: impl Into<Box<dyn Error>>) -> Self {
+        Self::Input(err.into())
+    }
+
+    fn output(err: impl Into<Box<dyn Error>>) -> Self {
+        Self::Output(err.into())
+    }
+
+    fn storage(err: impl Into<Box<dyn Error>>) -> Self {
+        Self::Storage(err.into())
+    }
+
+    fn exit_code(&self) -> u8 {
+        match self {
+            Self::Input(_) => EXIT_INPUT,
+            Self::Output(_) => EXIT_IO,
+            Self::Strict(_) => EXIT_STRICT,
+            Self::Storage(_) => EXIT_STORAGE,
+            Self::Audit(_) => EXIT_AUDIT,
+            Self::UnknownClient(_) => EXIT_UNKNOWN_CLIENT,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Input(err) => write!(f, "input: {err}"),
+            Self::Output(err) => write!(f, "output: {err}"),
+            Self::Strict(err) => write!(f, "{err}"),
+            Self::Storage(err) => write!(f, "storage: {err}"),
+            Self::Audit(reason) => write!(f, "audit: {reason}"),
+            Self::UnknownClient(client) => write!(f, "unknown client {client}"),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Failure>;
+
+fn main() -> ExitCode {
     env_logger::init();
 
-    let Some(file_location) = std::env::args().nth(1) else {
-        return Err("Please provide a file location".into());
+    let mut summary = RunSummary::new();
+    let (result, run) = match Cli::parse().command() {
+        Command::Process(args) => (process(&args, &mut summary), args.run),
+        Command::Validate(args) => (validate(&args, &mut summary), args.run),
+        Command::Replay(args) => (replay(&args, &mut summary), args.run),
+        Command::Inspect(args) => (inspect(&args, &mut summary), args.run),
+        Command::Statement(args) => (statement(&args, &mut summary), args.run),
+        Command::TrialBalance(args) => (trial_balance(&args, &mut summary), args.run),
+        Command::Audit(args) => {
+            return match audit(&args) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(err) => fail(err),
+            };
+        }
+        Command::Serve(args) => {
+            return match serve(&args) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(err) => fail(err),
+            };
+        }
     };
-    log::debug!("File location: {}", file_location);
 
-    let file = std::fs::File::open(file_location)?;
+    // The summary is written even if the run failed.
+    let written = write_summary(&run, &summary);
+    if let Err(err) = result {
+        if let Err(written) = written {
+            eprintln!("Error: {written}");
+        }
+        return fail(err);
+    }
+    if let Err(err) = written {
+        return fail(err);
+    }
+
+    if summary.rows_rejected > 0 {
+        ExitCode::from(EXIT_REJECTED)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
 
-    let mut csv_reader = csv::ReaderBuilder::new()
-        .flexible(true)
-        .trim(csv::Trim::All)
-        .has_headers(true)
-        .from_reader(file);
+/// Report the failure of a run.
+fn fail(err: Failure) -> ExitCode {
+    eprintln!("Error: {err}");
+    ExitCode::from(err.exit_code())
+}
 
-    let mut engine = PaymentEngine::new();
+/// Print the summary to stderr, or write it as JSON if requested.
+fn write_summary(run: &RunArgs, summary: &RunSummary) -> Result<()> {
+    match &run.summary {
+        Some(path) => {
+            let file = std::fs::File::create(path).map_err(Failure::output)?;
+            let mut writer = BufWriter::new(file);
+            serde_json::to_writer_pretty(&mut writer, summary).map_err(Failure::output)?;
+            writeln!(writer).map_err(Failure::output)
+        }
+        None => {
+            eprintln!("{summary}");
+            Ok(())
+        }
+    }
+}
 
-    for line in csv_reader.deserialize() {
-        let record: CsvTransaction = match line {
-            Ok(record) => record,
-            Err(err) => {
-                log::error!("Unprocessed line {err:?}");
-                continue;
+/// A run aborted by `--strict` at the first malformed or rejected row.
+#[derive(Debug)]
+struct StrictAbort {
+    source: String,
+    line: u64,
+    reason: String,
+}
+
+impl fmt::Display for StrictAbort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "aborted at {}:{} (strict mode): {}",
+            self.source, self.line, self.reason
+        )
+    }
+}
+
+impl std::error::Error for StrictAbort {}
+
+/// Abort the run on a rejected row in strict mode.
+fn check_strict(run: &RunArgs, rejection: &Rejection) -> Result<()> {
+    if !run.strict {
+        return Ok(());
+    }
+
+    Err(Failure::Strict(StrictAbort {
+        source: rejection.source.clone(),
+        line: rejection.line,
+        reason: rejection.reason.clone(),
+    }))
+}
+
+/// Abort the run on a rejected row in strict mode, otherwise record the row as
+/// rejected in the journal.
+fn check_rejection(
+    run: &RunArgs,
+    journal: &mut Option<Journal>,
+    rejection: &Rejection,
+) -> Result<()> {
+    check_strict(run, rejection)?;
+
+    if let Some(journal) = journal {
+        let record = JournalRecord::Rejected {
+            source: rejection.source.clone(),
+            line: rejection.line,
+        };
+        journal.append(&record).map_err(Failure::storage)?;
+    }
+    Ok(())
+}
+
+/// Call `f` for every row of the inputs, in order.
+fn for_each_row(input: &InputArgs, mut f: impl FnMut(&str, InputRow) -> Result<()>) -> Result<()> {
+    for source in &input.inputs {
+        log::debug!("File location: {}", source);
+
+        let reader: Box<dyn Read> = if source == "-" {
+            Box::new(std::io::stdin().lock())
+        } else {
+            Box::new(std::fs::File::open(source).map_err(Failure::input)?)
+        };
+        let format = input
+            .input_format
+            .unwrap_or_else(|| InputFormat::from_path(source));
+
+        for row in input::read_rows(reader, format).map_err(Failure::input)? {
+            f(source, row)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Open the engine, keeping its state in the `--storage` directory if set and
+/// restoring `--load-snapshot`.
+fn open_engine(args: &EngineArgs) -> Result<Engine> {
+    let storage: Box<dyn Storage> = match &args.storage {
+        Some(dir) => Box::new(FileStorage::open(dir).map_err(Failure::storage)?),
+        None => Box::new(MemoryStorage::new()),
+    };
+    let mut engine = PaymentEngine::with_storage(storage, args.config());
+
+    if let Some(path) = &args.load_snapshot {
+        let snapshot = read_snapshot(path).map_err(Failure::storage)?;
+        engine.restore(snapshot).map_err(Failure::storage)?;
+    }
+
+    Ok(engine)
+}
+
+/// Read the snapshot at the given path.
+fn read_snapshot(path: &Path) -> std::result::Result<EngineSnapshot, StorageErr> {
+    let file = std::fs::File::open(path)?;
+    EngineSnapshot::read(BufReader::new(file))
+}
+
+/// Rebuild the state of the engine from the `--journal`, returning the journal to
+/// append to and the rows of the inputs it already covers.
+///
+/// An unfinished run of the journal is resumed if the inputs are the same and
+/// unchanged. Otherwise a new run is started, which requires `--journal-restart`
+/// if an unfinished run is abandoned.
+fn open_journal(
+    engine: &mut Engine,
+    args: &EngineArgs,
+    input: &InputArgs,
+) -> Result<(Option<Journal>, Resume)> {
+    let Some(path) = &args.journal else {
+        return Ok((None, Resume::default()));
+    };
+
+    let records = Journal::recover(path).map_err(Failure::storage)?;
+    let entries = records.iter().filter_map(|record| match record {
+        JournalRecord::Applied(entry) => Some(entry),
+        _ => None,
+    });
+    engine.replay_journal(entries).map_err(Failure::storage)?;
+    log::info!("Replayed {} journal records", records.len());
+
+    let inputs = input
+        .inputs
+        .iter()
+        .map(|source| InputId::of(source))
+        .collect::<std::io::Result<Vec<_>>>()
+        .map_err(Failure::input)?;
+
+    // A journal written before runs were recorded has no start and is finished.
+    let start = records
+        .iter()
+        .rposition(|record| matches!(record, JournalRecord::Start { .. }));
+    let unfinished = start.filter(|_| records.last() != Some(&JournalRecord::Complete));
+
+    let mut journal = Journal::open(path, args.journal_commit).map_err(Failure::storage)?;
+    if let Some(start) = unfinished {
+        let JournalRecord::Start { inputs: previous } = &records[start] else {
+            unreachable!("a run begins with its start");
+        };
+        if *previous == inputs && inputs.iter().all(InputId::is_identified) {
+            let resume = Resume::new(&records[start + 1..]);
+            log::info!(
+                "Resuming the unfinished run after {} rows",
+                resume.remaining
+            );
+            return Ok((Some(journal), resume));
+        }
+
+        if !args.journal_restart {
+            return Err(Failure::storage(format!(
+                "{} has an unfinished run over other, changed or unidentifiable inputs; \
+                 rerun it with the same unchanged inputs or pass --journal-restart",
+                path.display()
+            )));
+        }
+        log::warn!(
+            "Abandoning the unfinished run of {} after {} records",
+            path.display(),
+            records.len() - start - 1
+        );
+    }
+
+    journal
+        .append(&JournalRecord::Start { inputs })
+        .map_err(Failure::storage)?;
+    Ok((Some(journal), Resume::default()))
+}
+
+/// The rows of the inputs recorded by an unfinished run of the journal.
+#[derive(Debug, Default)]
+struct Resume {
+    /// Number of rows still to skip.
+    remaining: u64,
+    /// The last recorded row.
+    last: Option<(String, u64)>,
+}
+
+impl Resume {
+    fn new(records: &[JournalRecord]) -> Self {
+        let mut resume = Self::default();
+        for record in records {
+            let row = match record {
+                JournalRecord::Applied(entry) => (entry.source.clone(), entry.line),
+                JournalRecord::Rejected { source, line } => (source.clone(), *line),
+                _ => continue,
+            };
+            resume.remaining += 1;
+            resume.last = Some(row);
+        }
+        resume
+    }
+
+    /// True if the row was recorded by the unfinished run and is skipped. Fails if
+    /// the last skipped row is not the last recorded one, i.e. the inputs are read
+    /// differently.
+    fn skip(&mut self, source: &str, line: u64) -> Result<bool> {
+        if self.remaining == 0 {
+            return Ok(false);
+        }
+
+        self.remaining -= 1;
+        if let (0, Some((last_source, last_line))) = (self.remaining, &self.last) {
+            if last_source != source || *last_line != line {
+                return Err(Failure::storage(format!(
+                    "row {source}:{line} differs from the last row \
+                     {last_source}:{last_line} of the journal"
+                )));
             }
+        }
+        Ok(true)
+    }
+}
+
+/// Mark the run of the journal as complete, so that it is not resumed.
+fn finish(journal: &mut Option<Journal>) -> Result<()> {
+    if let Some(journal) = journal {
+        journal
+            .append(&JournalRecord::Complete)
+            .map_err(Failure::storage)?;
+        journal.commit().map_err(Failure::storage)?;
+    }
+    Ok(())
+}
+
+/// Persist the state of the engine and commit the journal.
+fn commit(engine: &mut Engine, journal: &mut Option<Journal>) -> Result<()> {
+    engine.flush().map_err(Failure::storage)?;
+    if let Some(journal) = journal {
+        journal.commit().map_err(Failure::storage)?;
+    }
+    Ok(())
+}
+
+/// Persist the state of the engine and commit the journal, unless the run was
+/// aborted by `--strict`.
+fn commit_unless_aborted(
+    engine: &mut Engine,
+    journal: &mut Option<Journal>,
+    result: &Result<()>,
+) -> Result<()> {
+    match result {
+        Err(Failure::Strict(_)) => Ok(()),
+        _ => commit(engine, journal),
+    }
+}
+
+/// Write the state of the engine to `--save-snapshot`, if set.
+fn save_snapshot(engine: &Engine, args: &EngineArgs) -> Result<()> {
+    let Some(path) = &args.save_snapshot else {
+        return Ok(());
+    };
+
+    let snapshot = engine.snapshot().map_err(Failure::storage)?;
+    let file = std::fs::File::create(path).map_err(Failure::output)?;
+    snapshot
+        .write(BufWriter::new(file))
+        .map_err(Failure::output)
+}
+
+/// Apply the row to the engine, appending it to the journal if it was accepted, or
+/// recording it in the sink and summary if it was rejected.
+///
+/// Fails only if the state of the engine could not be loaded or stored.
+fn apply_row<'a>(
+    engine: &mut Engine,
+    journal: &mut Option<Journal>,
+    rejections: &'a mut RejectionSink,
+    summary: &mut RunSummary,
+    source: &str,
+    row: InputRow,
+) -> Result<std::result::Result<TransactionType, &'a Rejection>> {
+    let outcome = engine.process_row(row);
+    record_outcome(journal, rejections, summary, source, outcome)
+}
+
+/// Record the outcome of a row, like [`apply_row`].
+fn record_outcome<'a>(
+    journal: &mut Option<Journal>,
+    rejections: &'a mut RejectionSink,
+    summary: &mut RunSummary,
+    source: &str,
+    outcome: RowOutcome,
+) -> Result<std::result::Result<TransactionType, &'a Rejection>> {
+    let RowOutcome {
+        line,
+        raw,
+        transaction,
+        result,
+    } = outcome;
+
+    let delta = match result {
+        Ok(delta) => delta,
+        Err(RowErr::Input(err)) => {
+            log::error!("Unprocessed line {source}:{line}: {err}");
+            summary.malformed(err.code());
+            return Ok(Err(rejections.record(source, line, raw, None, err)));
+        }
+        Err(RowErr::Rejected(err)) => {
+            match err.kind() {
+                EngineErrKind::Storage(_) => return Err(Failure::storage(err)),
+                EngineErrKind::Client(ClientErr::InvariantViolated(_)) => {
+                    return Err(Failure::Audit(format!("{source}:{line}: {err}")));
+                }
+                _ => {}
+            }
+            log::error!("Error processing transaction {source}:{line}: {err}");
+            summary.rejected(err.code());
+            return Ok(Err(rejections.record(source, line, raw, transaction, err)));
+        }
+    };
+    let tx = transaction.expect("an applied row has a transaction");
+    log::trace!("{:?}", tx);
+
+    if let Some(journal) = journal {
+        let entry = JournalEntry {
+            source: source.to_string(),
+            line,
+            transaction: tx.clone(),
+            delta,
         };
+        journal
+            .append(&JournalRecord::Applied(entry))
+            .map_err(Failure::storage)?;
+    }
+
+    summary.applied();
+    Ok(Ok(tx))
+}
+
+/// Open the output of the command, stdout if no path is set.
+fn output_writer(args: &OutputArgs) -> Result<Box<dyn Write>> {
+    Ok(match &args.output {
+        Some(path) => Box::new(BufWriter::new(
+            std::fs::File::create(path).map_err(Failure::output)?,
+        )),
+        None => Box::new(BufWriter::new(std::io::stdout().lock())),
+    })
+}
+
+fn write_rejections(
+    rejections: &RejectionSink,
+    report: Option<(&str, RejectionFormat)>,
+) -> Result<()> {
+    if !rejections.rejections().is_empty() {
+        log::warn!("{} rows rejected", rejections.rejections().len());
+    }
+
+    if let Some((path, format)) = report {
+        let file = std::fs::File::create(path).map_err(Failure::output)?;
+        rejections
+            .write(BufWriter::new(file), format)
+            .map_err(Failure::output)?;
+    }
+
+    Ok(())
+}
+
+fn process(args: &ProcessArgs, summary: &mut RunSummary) -> Result<()> {
+    let mut engine = open_engine(&args.engine)?;
+    let (mut journal, mut resume) = open_journal(&mut engine, &args.engine, &args.input)?;
+    let mut rejections = RejectionSink::new();
+
+    let result = if args.threads > 1 {
+        process_sharded(
+            args,
+            &mut engine,
+            &mut journal,
+            &mut resume,
+            &mut rejections,
+            summary,
+        )
+    } else {
+        for_each_row(&args.input, |source, row| {
+            if resume.skip(source, row.line)? {
+                return Ok(());
+            }
+            if let Err(rejection) = apply_row(
+                &mut engine,
+                &mut journal,
+                &mut rejections,
+                summary,
+                source,
+                row,
+            )? {
+                check_rejection(&args.run, &mut journal, rejection)?;
+            }
+            Ok(())
+        })
+    };
+
+    // The rejection report and summary are written even if the run was aborted.
+    write_rejections(&rejections, args.rejections.report())?;
+    commit_unless_aborted(&mut engine, &mut journal, &result)?;
+    summary.count_accounts(&engine).map_err(Failure::storage)?;
+    result?;
+    save_snapshot(&engine, &args.engine)?;
+
+    engine
+        .serialize(output_writer(&args.output)?, &args.options())
+        .map_err(Failure::output)?;
+    finish(&mut journal)
+}
+
+/// Process the inputs on `--threads` shards in batches of rows, recording the
+/// outcomes in input order. The state of the shards is restored into the engine.
+///
+/// With `--strict` every row is a batch of its own, so that no row after the
+/// aborting one is processed.
+fn process_sharded(
+    args: &ProcessArgs,
+    engine: &mut Engine,
+    journal: &mut Option<Journal>,
+    resume: &mut Resume,
+    rejections: &mut RejectionSink,
+    summary: &mut RunSummary,
+) -> Result<()> {
+    let batch_size = if args.run.strict { 1 } else { 64 * 1024 };
+
+    let snapshot = engine.snapshot().map_err(Failure::storage)?;
+    let mut sharded = ShardedEngine::from_snapshot(snapshot, args.threads, args.engine.config())
+        .map_err(Failure::storage)?;
+
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut apply_batch = |batch: &mut Vec<(String, InputRow)>| -> Result<()> {
+        let txs: Vec<_> = batch
+            .iter()
+            .filter_map(|(_, row)| row.transaction.as_ref().ok().cloned())
+            .collect();
+        let mut outcomes = sharded
+            .process_batch(txs)
+            .map_err(Failure::storage)?
+            .into_iter();
+
+        for (source, row) in batch.drain(..) {
+            let (transaction, result) = match row.transaction {
+                Ok(tx) => {
+                    let outcome = outcomes.next().expect("an outcome per transaction");
+                    (Some(tx), outcome.map_err(RowErr::Rejected))
+                }
+                Err(err) => (None, Err(RowErr::Input(err))),
+            };
+            let outcome = RowOutcome {
+                line: row.line,
+                raw: row.raw,
+                transaction,
+                result,
+            };
+            if let Err(rejection) = record_outcome(journal, rejections, summary, &source, outcome)?
+            {
+                check_rejection(&args.run, journal, rejection)?;
+            }
+        }
+        Ok(())
+    };
+
+    let result = for_each_row(&args.input, |source, row| {
+        if resume.skip(source, row.line)? {
+            return Ok(());
+        }
+        batch.push((source.to_string(), row));
+        if batch.len() == batch_size {
+            apply_batch(&mut batch)?;
+        }
+        Ok(())
+    })
+    .and_then(|_| apply_batch(&mut batch));
+
+    let snapshot = sharded.snapshot().map_err(Failure::storage)?;
+    engine.restore(snapshot).map_err(Failure::storage)?;
+    result
+}
 
-        let tx = match TransactionType::try_from(record) {
-            Ok(tx) => tx,
+fn validate(args: &ValidateArgs, summary: &mut RunSummary) -> Result<()> {
+    let mut rejections = RejectionSink::new();
+
+    let result = for_each_row(&args.input, |source, row| {
+        match row.transaction {
+            Ok(_) => summary.parsed(),
             Err(err) => {
-                log::error!("Error processing transaction: {err}");
-                continue;
+                summary.malformed(err.code());
+                let rejection = rejections.record(source, row.line, row.raw, None, err);
+                check_strict(&args.run, rejection)?;
+            }
+        }
+        Ok(())
+    });
+
+    match args.rejections.report() {
+        Some(report) => write_rejections(&rejections, Some(report))?,
+        None => rejections
+            .write(std::io::stdout().lock(), RejectionFormat::Csv)
+            .map_err(Failure::output)?,
+    }
+
+    result
+}
+
+/// Process the inputs, for the commands only reporting the final state of the engine.
+fn run_engine(
+    input: &InputArgs,
+    run: &RunArgs,
+    args: &EngineArgs,
+    summary: &mut RunSummary,
+) -> Result<Engine> {
+    let mut engine = open_engine(args)?;
+    let (mut journal, mut resume) = open_journal(&mut engine, args, input)?;
+    let mut rejections = RejectionSink::new();
+
+    let result = for_each_row(input, |source, row| {
+        if resume.skip(source, row.line)? {
+            return Ok(());
+        }
+        if let Err(rejection) = apply_row(
+            &mut engine,
+            &mut journal,
+            &mut rejections,
+            summary,
+            source,
+            row,
+        )? {
+            check_rejection(run, &mut journal, rejection)?;
+        }
+        Ok(())
+    });
+    commit_unless_aborted(&mut engine, &mut journal, &result)?;
+    summary.count_accounts(&engine).map_err(Failure::storage)?;
+    result?;
+    save_snapshot(&engine, args)?;
+    finish(&mut journal)?;
+
+    Ok(engine)
+}
+
+/// Outcome of a single row written by `tx replay`.
+#[derive(Serialize)]
+struct ReplayRow {
+    source: String,
+    line: u64,
+    #[serde(rename = "type")]
+    ty: Option<&'static str>,
+    client: Option<u16>,
+    tx: Option<u32>,
+    amount: Option<String>,
+    status: &'static str,
+    reason: Option<String>,
+    available: Option<String>,
+    held: Option<String>,
+    total: Option<String>,
+    locked: Option<bool>,
+}
+
+fn replay(args: &ReplayArgs, summary: &mut RunSummary) -> Result<()> {
+    let mut engine = open_engine(&args.engine)?;
+    let (mut journal, mut resume) = open_journal(&mut engine, &args.engine, &args.input)?;
+    let mut rejections = RejectionSink::new();
+    let amounts = args.output.amounts();
+    let mut writer = csv::Writer::from_writer(output_writer(&args.output)?);
+
+    let result = for_each_row(&args.input, |source, row| {
+        if resume.skip(source, row.line)? {
+            return Ok(());
+        }
+        let line = row.line;
+        let outcome = apply_row(
+            &mut engine,
+            &mut journal,
+            &mut rejections,
+            summary,
+            source,
+            row,
+        )?;
+        let (tx, status, reason) = match outcome {
+            Ok(tx) => (Some(tx), "accepted", None),
+            Err(rejection) => {
+                check_rejection(&args.run, &mut journal, rejection)?;
+                (
+                    rejection.transaction.clone(),
+                    "rejected",
+                    Some(rejection.reason.clone()),
+                )
             }
         };
-        log::trace!("{:?}", tx);
 
-        engine.process_transaction(tx);
+        let account = match &tx {
+            Some(tx) => engine.account(tx.client_id()).map_err(Failure::storage)?,
+            None => None,
+        };
+        let row = ReplayRow {
+            source: source.to_string(),
+            line,
+            ty: tx.as_ref().map(TransactionType::type_name),
+            client: tx.as_ref().map(TransactionType::client_id),
+            tx: tx.as_ref().map(TransactionType::transaction_id),
+            amount: tx
+                .as_ref()
+                .and_then(TransactionType::amount)
+                .map(|a| a.to_string()),
+            status,
+            reason,
+            available: account.as_ref().map(|a| amounts.format(a.available())),
+            held: account.as_ref().map(|a| amounts.format(a.held())),
+            total: account.as_ref().map(|a| amounts.format(a.total())),
+            locked: account.as_ref().map(|a| a.is_locked()),
+        };
+        writer.serialize(row).map_err(Failure::output)
+    });
+
+    // The outcomes up to an aborted row are written.
+    writer.flush().map_err(Failure::output)?;
+    commit_unless_aborted(&mut engine, &mut journal, &result)?;
+    summary.count_accounts(&engine).map_err(Failure::storage)?;
+    result?;
+    save_snapshot(&engine, &args.engine)?;
+    finish(&mut journal)
+}
+
+/// A processed transaction of the client shown by `tx inspect`.
+#[derive(Serialize)]
+struct InspectTransaction {
+    tx: u32,
+    #[serde(rename = "type")]
+    ty: &'static str,
+    amount: Option<String>,
+    state: DisputeState,
+}
+
+/// The report written by `tx inspect`.
+#[derive(Serialize)]
+struct InspectReport {
+    account: AccountSnapshot,
+    transactions: Vec<InspectTransaction>,
+}
+
+fn inspect(args: &InspectArgs, summary: &mut RunSummary) -> Result<()> {
+    let engine = run_engine(&args.input, &args.run, &args.engine, summary)?;
+
+    let Some(account) = engine.account(args.client).map_err(Failure::storage)? else {
+        return Err(Failure::UnknownClient(args.client));
+    };
+
+    let amounts = args.output.amounts();
+    let mut transactions: Vec<_> = account
+        .transactions()
+        .map(|(tx, state)| InspectTransaction {
+            tx: tx.transaction_id(),
+            ty: tx.type_name(),
+            amount: tx.amount().map(|a| amounts.format(a)),
+            state,
+        })
+        .collect();
+    transactions.sort_by_key(|tx| tx.tx);
+
+    let report = InspectReport {
+        account: AccountSnapshot::new(&account, &amounts),
+        transactions,
+    };
+
+    let writer = output_writer(&args.output)?;
+    write_inspect_report(writer, &report, args.format).map_err(Failure::output)
+}
+
+fn write_inspect_report(
+    mut writer: impl Write,
+    report: &InspectReport,
+    format: OutputFormat,
+) -> std::result::Result<(), Box<dyn Error>> {
+    match format {
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, report)?;
+            writeln!(writer)?;
+        }
+        OutputFormat::JsonLines => {
+            serde_json::to_writer(&mut writer, report)?;
+            writeln!(writer)?;
+        }
+        OutputFormat::Csv => unreachable!("csv is rejected by the command line"),
+        OutputFormat::Table => {
+            output::write_snapshots(
+                &mut writer,
+                std::slice::from_ref(&report.account),
+                OutputFormat::Table,
+            )?;
+            writeln!(writer)?;
+
+            let rows: Vec<Vec<String>> = report
+                .transactions
+                .iter()
+                .map(|tx| {
+                    vec![
+                        tx.tx.to_string(),
+                        tx.ty.to_string(),
+                        tx.amount.clone().unwrap_or_default(),
+                        format!("{:?}", tx.state),
+                    ]
+                })
+                .collect();
+            output::write_aligned(&mut writer, &["tx", "type", "amount", "state"], &rows)?;
+        }
     }
 
-    engine.serialize()?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn statement(args: &StatementArgs, summary: &mut RunSummary) -> Result<()> {
+    let engine = run_engine(&args.input, &args.run, &args.engine, summary)?;
+
+    let Some(account) = engine.account(args.client).map_err(Failure::storage)? else {
+        return Err(Failure::UnknownClient(args.client));
+    };
+
+    let amounts = args.output.amounts();
+    let lines: Vec<_> = account
+        .statement()
+        .take_while(|(event, _)| args.as_of.is_none_or(|seq| event.seq <= seq))
+        .map(|(event, balances)| StatementLine::new(event, &balances, &amounts))
+        .collect();
+
+    let writer = output_writer(&args.output)?;
+    write_statement(writer, &lines, args.format).map_err(Failure::output)
+}
 
+fn write_statement(
+    mut writer: impl Write,
+    lines: &[StatementLine],
+    format: OutputFormat,
+) -> std::result::Result<(), Box<dyn Error>> {
+    match format {
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(&mut writer);
+            for line in lines {
+                writer.serialize(line)?;
+            }
+            writer.flush()?;
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, lines)?;
+            writeln!(writer)?;
+        }
+        OutputFormat::JsonLines => {
+            for line in lines {
+                serde_json::to_writer(&mut writer, line)?;
+                writeln!(writer)?;
+            }
+        }
+        OutputFormat::Table => {
+            let rows: Vec<Vec<String>> = lines
+                .iter()
+                .map(|line| {
+                    vec![
+                        line.seq.to_string(),
+                        format!("{:?}", line.kind),
+                        line.tx.to_string(),
+                        line.available_change.to_string(),
+                        line.held_change.to_string(),
+                        line.total_change.to_string(),
+                        line.available.to_string(),
+                        line.held.to_string(),
+                        line.total.to_string(),
+                        line.locked.to_string(),
+                    ]
+                })
+                .collect();
+            output::write_aligned(
+                &mut writer,
+                &[
+                    "seq",
+                    "kind",
+                    "tx",
+                    "available_change",
+                    "held_change",
+                    "total_change",
+                    "available",
+                    "held",
+                    "total",
+                    "locked",
+                ],
+                &rows,
+            )?;
+        }
+    }
+
+    writer.flush()?;
     Ok(())
 }
+
+fn trial_balance(args: &TrialBalanceArgs, summary: &mut RunSummary) -> Result<()> {
+    let engine = run_engine(&args.input, &args.run, &args.engine, summary)?;
+    let trial = engine.trial_balance().map_err(Failure::storage)?;
+
+    let amounts = args.output.amounts();
+    let mut rows: Vec<Vec<String>> = trial
+        .balances
+        .iter()
+        .map(|(account, balance)| vec![account.to_string(), amounts.format(*balance)])
+        .collect();
+    rows.push(vec!["sum".to_string(), amounts.format(trial.sum())]);
+
+    let mut writer = output_writer(&args.output)?;
+    output::write_aligned(&mut writer, &["account", "balance"], &rows)
+        .and_then(|_| writer.flush())
+        .map_err(Failure::output)?;
+
+    if !trial.sum().is_zero() {
+        return Err(Failure::Audit(format!("the books sum to {}", trial.sum())));
+    }
+    if !trial.mismatched_clients.is_empty() {
+        return Err(Failure::Audit(format!(
+            "balances of clients {:?} differ from the books",
+            trial.mismatched_clients
+        )));
+    }
+
+    Ok(())
+}
+
+fn audit(args: &AuditArgs) -> Result<()> {
+    // The snapshot is audited even if it cannot be restored.
+    let file = std::fs::File::open(&args.snapshot).map_err(Failure::input)?;
+    let snapshot = EngineSnapshot::read_unchecked(BufReader::new(file)).map_err(Failure::input)?;
+    let violations = invariant::audit(&snapshot.accounts);
+
+    let mut writer = std::io::stdout().lock();
+    for violation in &violations {
+        writeln!(writer, "{}: {violation}", violation.code()).map_err(Failure::output)?;
+    }
+
+    if !violations.is_empty() {
+        return Err(Failure::Audit(format!(
+            "{} violations in {} accounts",
+            violations.len(),
+            snapshot.accounts.len()
+        )));
+    }
+
+    writeln!(
+        writer,
+        "{} accounts, no violations",
+        snapshot.accounts.len()
+    )
+    .map_err(Failure::output)
+}
+
+fn serve(args: &ServeArgs) -> Result<()> {
+    let config = args.config.config();
+
+    // The journal holds the whole state, and cannot be combined with a snapshot.
+    let engine = match &args.journal {
+        Some(path) => {
+            let records = Journal::recover(path).map_err(Failure::storage)?;
+            let entries: Vec<_> = records
+                .iter()
+                .filter_map(|record| match record {
+                    JournalRecord::Applied(entry) => Some(entry),
+                    _ => None,
+                })
+                .collect();
+
+            let mut engine = PaymentEngine::with_config(config);
+            engine
+                .replay_journal(entries.iter().copied())
+                .map_err(Failure::storage)?;
+            log::info!("Replayed {} journal entries", entries.len());
+
+            let snapshot = engine.snapshot().map_err(Failure::storage)?;
+            let journal = Journal::open(path, args.journal_commit).map_err(Failure::storage)?;
+            SharedEngine::with_journal(snapshot, config, journal, entries.len() as u64)
+        }
+        None => {
+            let snapshot = match &args.load_snapshot {
+                Some(path) => read_snapshot(path).map_err(Failure::storage)?,
+                None => EngineSnapshot::new(Vec::new()),
+            };
+            SharedEngine::from_snapshot(snapshot, config)
+        }
+    }
+    .map_err(Failure::storage)?;
+
+    if let Some(path) = args.listen.strip_prefix("unix:") {
+        #[cfg(unix)]
+        {
+            let listener = server::bind_unix(path).map_err(Failure::output)?;
+            log::info!("Listening on {path}");
+            return server::serve_unix(listener, engine, args.protocol).map_err(Failure::output);
+        }
+        #[cfg(not(unix))]
+        return Err(Failure::output(format!(
+            "Unix sockets are not supported on this platform: {path}"
+        )));
+    }
+
+    let listener = std::net::TcpListener::bind(&args.listen).map_err(Failure::output)?;
+    log::info!("Listening on {}", args.listen);
+    server::serve_tcp(listener, engine, args.protocol).map_err(Failure::output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const INPUT: &str = "type,client,tx,amount
+deposit,1,1,5.0
+withdrawal,1,2,9.0
+deposit,2,3,1.0
+";
+
+    fn run_process(args: &[&str]) -> (Result<()>, RunSummary) {
+        let cli = Cli::try_parse_from([&["tx", "process"], args].concat()).unwrap();
+        let Command::Process(args) = cli.command() else {
+            unreachable!("the command is process");
+        };
+        let mut summary = RunSummary::new();
+        (process(&args, &mut summary), summary)
+    }
+
+    /// Drop the records of the journal after the first `len`, as if the run crashed.
+    fn truncate_journal(path: &Path, len: usize) {
+        let content = fs::read_to_string(path).unwrap();
+        let lines: Vec<_> = content.lines().take(len).collect();
+        fs::write(path, lines.join("\n") + "\n").unwrap();
+    }
+
+    #[test]
+    fn test_journal_resume() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.csv");
+        let journal = dir.path().join("journal.jsonl");
+        let output = dir.path().join("accounts.csv");
+        fs::write(&input, INPUT).unwrap();
+        let args = [
+            input.to_str().unwrap(),
+            "--journal",
+            journal.to_str().unwrap(),
+            "--output",
+            output.to_str().unwrap(),
+        ];
+
+        let (result, summary) = run_process(&args);
+        result.unwrap();
+        assert_eq!((summary.rows_applied, summary.rows_rejected), (2, 1));
+        // The start, the three rows and the completion of the run.
+        assert_eq!(Journal::recover(&journal).unwrap().len(), 5);
+
+        // A crash after the rejected row resumes after it.
+        truncate_journal(&journal, 3);
+        let (result, summary) = run_process(&args);
+        result.unwrap();
+        assert_eq!((summary.rows_applied, summary.rows_rejected), (1, 0));
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "client,available,held,total,locked\n\
+             1,5.0000,0.0000,5.0000,false\n\
+             2,1.0000,0.0000,1.0000,false\n"
+        );
+        assert_eq!(
+            Journal::recover(&journal).unwrap().last(),
+            Some(&JournalRecord::Complete)
+        );
+
+        // The input of a finished run is replaced by the next day's rows.
+        fs::write(&input, "type,client,tx,amount\ndeposit,1,4,2.0\n").unwrap();
+        let (result, summary) = run_process(&args);
+        result.unwrap();
+        assert_eq!((summary.rows_applied, summary.rows_rejected), (1, 0));
+        assert!(fs::read_to_string(&output)
+            .unwrap()
+            .contains("1,7.0000,0.0000,7.0000,false"));
+    }
+
+    #[test]
+    fn test_journal_changed_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.csv");
+        let journal = dir.path().join("journal.jsonl");
+        let output = dir.path().join("accounts.csv");
+        fs::write(&input, INPUT).unwrap();
+        let args = [
+            input.to_str().unwrap(),
+            "--journal",
+            journal.to_str().unwrap(),
+            "--output",
+            output.to_str().unwrap(),
+        ];
+
+        // An aborted run is unfinished and aborts again at the same row.
+        let strict = [&args[..], &["--strict"]].concat();
+        let (result, _) = run_process(&strict);
+        assert!(matches!(result, Err(Failure::Strict(_))));
+        let (result, summary) = run_process(&strict);
+        assert!(matches!(result, Err(Failure::Strict(abort)) if abort.line == 3));
+        assert_eq!(summary.rows_applied, 0);
+
+        // The unfinished run is not resumed over a changed input.
+        fs::write(&input, INPUT.replace("9.0", "1.0")).unwrap();
+        let (result, _) = run_process(&args);
+        assert!(matches!(result, Err(Failure::Storage(_))));
+
+        let restart = [&args[..], &["--journal-restart"]].concat();
+        let (result, summary) = run_process(&restart);
+        result.unwrap();
+        // The deposit already applied by the abandoned run is a duplicate.
+        assert_eq!((summary.rows_applied, summary.rows_rejected), (2, 1));
+    }
+
+    #[test]
+    fn test_journal_stdin() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.jsonl");
+        let mut journal = Journal::open(&path, 1).unwrap();
+        let inputs = vec![InputId::of("-").unwrap()];
+        journal.append(&JournalRecord::Start { inputs }).unwrap();
+        drop(journal);
+
+        let open = |restart: &[&str]| {
+            let args = [
+                &["tx", "process", "-", "--journal", path.to_str().unwrap()],
+                restart,
+            ];
+            let Command::Process(args) = Cli::try_parse_from(args.concat()).unwrap().command()
+            else {
+                unreachable!("the command is process");
+            };
+            let mut engine = open_engine(&args.engine).unwrap();
+            open_journal(&mut engine, &args.engine, &args.input).map(|(_, resume)| resume)
+        };
+
+        // Stdin cannot be read again, so its run is not resumed.
+        assert!(matches!(open(&[]), Err(Failure::Storage(_))));
+        assert_eq!(open(&["--journal-restart"]).unwrap().remaining, 0);
+        // The restarted run over stdin is unfinished as well.
+        assert!(matches!(open(&[]), Err(Failure::Storage(_))));
+    }
+
+    #[test]
+    fn test_strict_sharded() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.csv");
+        fs::write(&input, INPUT).unwrap();
+
+        let run = |threads: &[&str]| {
+            let journal = dir.path().join(format!("journal{}.jsonl", threads.len()));
+            let args = [
+                &[input.to_str().unwrap(), "--strict", "--journal"],
+                &[journal.to_str().unwrap()][..],
+                threads,
+            ]
+            .concat();
+            let (result, summary) = run_process(&args);
+            assert!(matches!(result, Err(Failure::Strict(_))));
+            (summary, Journal::recover(&journal).unwrap())
+        };
+
+        // The deposit after the aborting withdrawal is not processed by a shard.
+        let sequential = run(&[]);
+        assert_eq!(sequential.0.accounts_touched, 1);
+        assert_eq!(run(&["--threads", "2"]), sequential);
+    }
+
+    #[test]
+    fn test_resume_skip() {
+        let mut resume = Resume::new(&[
+            JournalRecord::Start { inputs: Vec::new() },
+            JournalRecord::Rejected {
+                source: "a.csv".into(),
+                line: 2,
+            },
+            JournalRecord::Rejected {
+                source: "a.csv".into(),
+                line: 4,
+            },
+        ]);
+        assert!(resume.skip("a.csv", 2).unwrap());
+        // The inputs yield other rows than the recorded ones.
+        assert!(matches!(resume.skip("a.csv", 3), Err(Failure::Storage(_))));
+
+        let mut resume = Resume::new(&[JournalRecord::Rejected {
+            source: "a.csv".into(),
+            line: 2,
+        }]);
+        assert!(resume.skip("a.csv", 2).unwrap());
+        assert!(!resume.skip("a.csv", 3).unwrap());
+    }
+}
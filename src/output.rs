@@ -0,0 +1,318 @@
+use crate::{client::ClientAccount, engine::AccountOrder, types::PRECISION};
+
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+use std::{io::Write, str::FromStr};
+
+/// Format of the account output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// CSV with a header row.
+    #[default]
+    Csv,
+    /// A single JSON array.
+    Json,
+    /// One JSON object per line.
+    JsonLines,
+    /// An aligned table for humans.
+    Table,
+}
+
+impl FromStr for OutputFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "jsonl" => Ok(Self::JsonLines),
+            "table" => Ok(Self::Table),
+            _ => Err("Unknown output format"),
+        }
+    }
+}
+
+/// Rounding applied when an amount has more decimal places than the output scale.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Rounding {
+    /// Round half to even (banker's rounding).
+    #[default]
+    HalfEven,
+    /// Round half away from zero.
+    HalfUp,
+    /// Truncate towards zero.
+    Down,
+    /// Round away from zero.
+    Up,
+}
+
+impl Rounding {
+    fn strategy(self) -> RoundingStrategy {
+        match self {
+            Self::HalfEven => RoundingStrategy::MidpointNearestEven,
+            Self::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Self::Down => RoundingStrategy::ToZero,
+            Self::Up => RoundingStrategy::AwayFromZero,
+        }
+    }
+}
+
+impl FromStr for Rounding {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "half-even" => Ok(Self::HalfEven),
+            "half-up" => Ok(Self::HalfUp),
+            "down" => Ok(Self::Down),
+            "up" => Ok(Self::Up),
+            _ => Err("Unknown rounding strategy"),
+        }
+    }
+}
+
+/// Formatting of the amounts in the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AmountFormat {
+    /// Number of decimal places of every amount.
+    pub scale: u32,
+    /// Rounding applied to amounts with more decimal places than `scale`.
+    pub rounding: Rounding,
+}
+
+impl Default for AmountFormat {
+    fn default() -> Self {
+        Self {
+            scale: PRECISION,
+            rounding: Rounding::default(),
+        }
+    }
+}
+
+impl AmountFormat {
+    /// Round the amount to exactly `scale` decimal places.
+    pub fn apply(&self, amount: Decimal) -> Decimal {
+        let mut amount = amount.round_dp_with_strategy(self.scale, self.rounding.strategy());
+        amount.rescale(self.scale);
+        amount
+    }
+
+    /// Format the amount with exactly `scale` decimal places.
+    pub fn format(&self, amount: Decimal) -> String {
+        self.apply(amount).to_string()
+    }
+}
+
+/// The state of an account as written to the output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountSnapshot {
+    pub client: u16,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl AccountSnapshot {
+    /// Take a snapshot of the account, formatting the amounts.
+    pub fn new(account: &ClientAccount, amounts: &AmountFormat) -> Self {
+        Self {
+            client: account.client(),
+            available: amounts.apply(account.available()),
+            held: amounts.apply(account.held()),
+            total: amounts.apply(account.total()),
+            locked: account.is_locked(),
+        }
+    }
+}
+
+/// Write the snapshots in the given format.
+pub fn write_snapshots<W: Write>(
+    mut writer: W,
+    snapshots: &[AccountSnapshot],
+    format: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match format {
+        OutputFormat::Csv => {
+            // Write the header explicitly so that it is present without accounts.
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(&mut writer);
+            writer.write_record(["client", "available", "held", "total", "locked"])?;
+            for snapshot in snapshots {
+                writer.serialize(snapshot)?;
+            }
+            writer.flush()?;
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, snapshots)?;
+            writer.write_all(b"\n")?;
+        }
+        OutputFormat::JsonLines => {
+            for snapshot in snapshots {
+                serde_json::to_writer(&mut writer, snapshot)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        OutputFormat::Table => write_table(&mut writer, snapshots)?,
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_table<W: Write>(writer: &mut W, snapshots: &[AccountSnapshot]) -> std::io::Result<()> {
+    let rows: Vec<Vec<String>> = snapshots
+        .iter()
+        .map(|s| {
+            vec![
+                s.client.to_string(),
+                s.available.to_string(),
+                s.held.to_string(),
+                s.total.to_string(),
+                s.locked.to_string(),
+            ]
+        })
+        .collect();
+
+    write_aligned(
+        writer,
+        &["client", "available", "held", "total", "locked"],
+        &rows,
+    )
+}
+
+/// Write the rows as a right-aligned table with the given header.
+pub fn write_aligned<W: Write>(
+    writer: &mut W,
+    header: &[&str],
+    rows: &[Vec<String>],
+) -> std::io::Result<()> {
+    let mut widths: Vec<usize> = header.iter().map(|cell| cell.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let header: Vec<String> = header.iter().map(|cell| cell.to_string()).collect();
+    for row in std::iter::once(&header).chain(rows) {
+        let line: Vec<_> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:>width$}"))
+            .collect();
+        writeln!(writer, "{}", line.join("  "))?;
+    }
+
+    Ok(())
+}
+
+/// Options controlling the serialized account output.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OutputOptions {
+    /// Order of the accounts.
+    pub order: AccountOrder,
+    /// Formatting of the amounts.
+    pub amounts: AmountFormat,
+    /// Format of the output.
+    pub format: OutputFormat,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_scale() {
+        let format = AmountFormat::default();
+        assert_eq!(format.format(Decimal::from_str("1.0").unwrap()), "1.0000");
+        assert_eq!(format.format(Decimal::from_str("3").unwrap()), "3.0000");
+        assert_eq!(
+            format.format(Decimal::from_str("0.0000").unwrap()),
+            "0.0000"
+        );
+        assert_eq!(format.format(Decimal::from_str("-2.5").unwrap()), "-2.5000");
+    }
+
+    #[test]
+    fn test_rounding() {
+        let amount = Decimal::from_str("2.125").unwrap();
+        let format = |rounding| AmountFormat { scale: 2, rounding }.format(amount);
+
+        assert_eq!(format(Rounding::HalfEven), "2.12");
+        assert_eq!(format(Rounding::HalfUp), "2.13");
+        assert_eq!(format(Rounding::Down), "2.12");
+        assert_eq!(format(Rounding::Up), "2.13");
+
+        let format = AmountFormat {
+            scale: 0,
+            rounding: Rounding::HalfEven,
+        };
+        assert_eq!(format.format(Decimal::from_str("2.5").unwrap()), "2");
+    }
+
+    fn snapshots() -> Vec<AccountSnapshot> {
+        vec![
+            AccountSnapshot {
+                client: 1,
+                available: Decimal::from_str("10.5000").unwrap(),
+                held: Decimal::from_str("0.0000").unwrap(),
+                total: Decimal::from_str("10.5000").unwrap(),
+                locked: false,
+            },
+            AccountSnapshot {
+                client: 12,
+                available: Decimal::from_str("0.0000").unwrap(),
+                held: Decimal::from_str("1.0000").unwrap(),
+                total: Decimal::from_str("1.0000").unwrap(),
+                locked: true,
+            },
+        ]
+    }
+
+    fn write(snapshots: &[AccountSnapshot], format: OutputFormat) -> String {
+        let mut out = Vec::new();
+        write_snapshots(&mut out, snapshots, format).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_write_csv() {
+        assert_eq!(
+            write(&snapshots(), OutputFormat::Csv),
+            "client,available,held,total,locked\n\
+             1,10.5000,0.0000,10.5000,false\n\
+             12,0.0000,1.0000,1.0000,true\n"
+        );
+        assert_eq!(
+            write(&[], OutputFormat::Csv),
+            "client,available,held,total,locked\n"
+        );
+    }
+
+    #[test]
+    fn test_write_json() {
+        let out = write(&snapshots(), OutputFormat::Json);
+        let parsed: Vec<AccountSnapshot> = serde_json::from_str(&out).unwrap();
+        assert_eq!(parsed, snapshots());
+
+        let out = write(&snapshots(), OutputFormat::JsonLines);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines[0],
+            r#"{"client":1,"available":"10.5000","held":"0.0000","total":"10.5000","locked":false}"#
+        );
+        assert_eq!(lines.len(), 2);
+    }
+
+    #[test]
+    fn test_write_table() {
+        assert_eq!(
+            write(&snapshots(), OutputFormat::Table),
+            "client  available    held    total  locked\n\
+             \x20    1    10.5000  0.0000  10.5000   false\n\
+             \x20   12     0.0000  1.0000   1.0000    true\n"
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use crate::{error::EngineErr, input::InputErr, stream::RowErr, types::TransactionType};
+
+use serde::Serialize;
+use std::{io::Write, str::FromStr};
+
+/// Output format of the rejection report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectionFormat {
+    Csv,
+    /// A single JSON array of the rejections.
+    Json,
+    JsonLines,
+}
+
+impl RejectionFormat {
+    /// Guess the format from the extension of the given path, defaulting to CSV.
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".jsonl") || path.ends_with(".ndjson") {
+            Self::JsonLines
+        } else if path.ends_with(".json") {
+            Self::Json
+        } else {
+            Self::Csv
+        }
+    }
+}
+
+impl FromStr for RejectionFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "jsonl" => Ok(Self::JsonLines),
+            _ => Err("Unknown rejection format"),
+        }
+    }
+}
+
+/// A row of the input that was refused, either while parsing or by the engine.
+#[derive(Debug, Clone, Serialize)]
+pub struct Rejection {
+    /// The input the row was read from, `-` for stdin.
+    pub source: String,
+    /// Line number of the row in the input.
+    pub line: u64,
+    /// Raw content of the row.
+    pub raw: String,
+    /// The parsed transaction, if parsing succeeded.
+    pub transaction: Option<TransactionType>,
+    /// Machine-readable code of the reason.
+    pub code: &'static str,
+    /// The reason the row was refused.
+    pub reason: String,
+}
+
+/// An error that can be recorded as the reason of a [`Rejection`].
+pub trait RejectionReason: std::fmt::Display {
+    /// A stable, machine-readable code of the reason.
+    fn code(&self) -> &'static str;
+}
+
+impl RejectionReason for EngineErr {
+    fn code(&self) -> &'static str {
+        EngineErr::code(self)
+    }
+}
+
+impl RejectionReason for InputErr {
+    fn code(&self) -> &'static str {
+        InputErr::code(self)
+    }
+}
+
+impl RejectionReason for RowErr {
+    fn code(&self) -> &'static str {
+        RowErr::code(self)
+    }
+}
+
+/// Collects the rejected rows of a run.
+#[derive(Debug, Default)]
+pub struct RejectionSink {
+    rejections: Vec<Rejection>,
+}
+
+impl RejectionSink {
+    /// Constructs a new, empty [`RejectionSink`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a rejected row.
+    pub fn record(
+        &mut self,
+        source: &str,
+        line: u64,
+        raw: String,
+        transaction: Option<TransactionType>,
+        reason: impl RejectionReason,
+    ) -> &Rejection {
+        self.rejections.push(Rejection {
+            source: source.to_string(),
+            line,
+            raw,
+            transaction,
+            code: reason.code(),
+            reason: reason.to_string(),
+        });
+        &self.rejections[self.rejections.len() - 1]
+    }
+
+    /// The rejections recorded so far.
+    pub fn rejections(&self) -> &[Rejection] {
+        &self.rejections
+    }
+
+    /// Write the rejections in the given format.
+    pub fn write<W: Write>(
+        &self,
+        writer: W,
+        format: RejectionFormat,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match format {
+            RejectionFormat::Csv => self.write_csv(writer),
+            RejectionFormat::Json => self.write_json(writer),
+            RejectionFormat::JsonLines => self.write_jsonl(writer),
+        }
+    }
+
+    fn write_csv<W: Write>(&self, writer: W) -> Result<(), Box<dyn std::error::Error>> {
+        let mut writer = csv::Writer::from_writer(writer);
+
+        writer.write_record([
+            "source", "line", "raw", "type", "client", "tx", "amount", "code", "reason",
+        ])?;
+
+        for rejection in &self.rejections {
+            let (ty, client, tx, amount) = match &rejection.transaction {
+                Some(tx) => (
+                    tx.type_name().to_string(),
+                    tx.client_id().to_string(),
+                    tx.transaction_id().to_string(),
+                    tx.amount().map(|a| a.to_string()).unwrap_or_default(),
+                ),
+                None => Default::default(),
+            };
+
+            writer.write_record(&[
+                rejection.source.clone(),
+                rejection.line.to_string(),
+                rejection.raw.clone(),
+                ty,
+                client,
+                tx,
+                amount,
+                rejection.code.to_string(),
+                rejection.reason.clone(),
+            ])?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn write_json<W: Write>(&self, mut writer: W) -> Result<(), Box<dyn std::error::Error>> {
+        serde_json::to_writer(&mut writer, &self.rejections)?;
+        writer.write_all(b"\n")?;
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn write_jsonl<W: Write>(&self, mut writer: W) -> Result<(), Box<dyn std::error::Error>> {
+        for rejection in &self.rejections {
+            serde_json::to_writer(&mut writer, rejection)?;
+            writer.write_all(b"\n")?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientErr;
+    use rust_decimal::Decimal;
+
+    fn sink() -> RejectionSink {
+        let mut sink = RejectionSink::new();
+        sink.record(
+            "a.csv",
+            2,
+            "fewfew,".into(),
+            None,
+            InputErr::Malformed("missing field `client`".into()),
+        );
+        sink.record(
+            "b.csv",
+            3,
+            "withdrawal,1,2,5.0".into(),
+            Some(TransactionType::Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: Decimal::new(50, 1),
+            }),
+            EngineErr::new(1, 2, ClientErr::InsufficientFunds),
+        );
+        sink
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let mut out = Vec::new();
+        sink().write(&mut out, RejectionFormat::Csv).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "source,line,raw,type,client,tx,amount,code,reason\n\
+             a.csv,2,\"fewfew,\",,,,,malformed,malformed row: missing field `client`\n\
+             b.csv,3,\"withdrawal,1,2,5.0\",withdrawal,1,2,5.0,insufficient_funds,\
+             [client 1 tx 2] insufficient funds\n"
+        );
+    }
+
+    #[test]
+    fn test_write_jsonl() {
+        let mut out = Vec::new();
+        sink().write(&mut out, RejectionFormat::JsonLines).unwrap();
+
+        let lines: Vec<serde_json::Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["source"], "a.csv");
+        assert_eq!(lines[0]["line"], 2);
+        assert!(lines[0]["transaction"].is_null());
+        assert_eq!(lines[1]["code"], "insufficient_funds");
+        assert_eq!(lines[1]["reason"], "[client 1 tx 2] insufficient funds");
+        assert_eq!(lines[1]["transaction"]["Withdrawal"]["client"], 1);
+    }
+
+    #[test]
+    fn test_write_json() {
+        let mut out = Vec::new();
+        sink().write(&mut out, RejectionFormat::Json).unwrap();
+
+        let rejections: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(rejections.as_array().unwrap().len(), 2);
+        assert_eq!(rejections[1]["code"], "insufficient_funds");
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(RejectionFormat::from_path("out.csv"), RejectionFormat::Csv);
+        assert_eq!(
+            RejectionFormat::from_path("out.json"),
+            RejectionFormat::Json
+        );
+        assert_eq!(
+            RejectionFormat::from_path("out.jsonl"),
+            RejectionFormat::JsonLines
+        );
+        assert_eq!(
+            RejectionFormat::from_path("out.ndjson"),
+            RejectionFormat::JsonLines
+        );
+    }
+}
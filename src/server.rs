@@ -0,0 +1,485 @@
+use crate::{
+    http,
+    input::{self, InputFormat},
+    output::{AccountSnapshot, AmountFormat},
+    shared::SharedEngine,
+    stream::RowErr,
+};
+
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    net::TcpListener,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+/// Maximum length of a request line in bytes, including the line break.
+pub const MAX_LINE: u64 = 64 * 1024;
+
+/// Maximum number of connections answered at the same time. Further connections
+/// are closed right away.
+pub const MAX_CONNECTIONS: usize = 1024;
+
+/// Protocol spoken on the connections of a server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Protocol {
+    /// One reply line per request line, see [`reply`].
+    #[default]
+    Lines,
+    /// HTTP with JSON bodies, see [`http::route`].
+    Http,
+}
+
+impl Protocol {
+    /// Answer the requests read from a connection.
+    pub fn handle_connection<R: Read, W: Write>(
+        self,
+        engine: &SharedEngine,
+        reader: R,
+        writer: W,
+    ) -> io::Result<()> {
+        match self {
+            Self::Lines => handle_connection(engine, reader, writer),
+            Self::Http => http::handle_connection(engine, reader, writer),
+        }
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lines" => Ok(Self::Lines),
+            "http" => Ok(Self::Http),
+            _ => Err("Unknown protocol"),
+        }
+    }
+}
+
+/// The reply to a request line of the serve protocol.
+#[derive(Debug)]
+pub enum Reply {
+    /// The transaction was applied.
+    Accepted,
+    /// The transaction was malformed or rejected.
+    Rejected(RowErr),
+    /// The balances of the queried client.
+    Balance(AccountSnapshot),
+    /// The request is not understood or refers to an unknown client.
+    Error { code: &'static str, reason: String },
+}
+
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Accepted => write!(f, "accepted"),
+            Self::Rejected(err) => write!(f, "rejected {} {err}", err.code()),
+            Self::Balance(account) => write!(
+                f,
+                "balance {} {} {} {} {}",
+                account.client, account.available, account.held, account.total, account.locked
+            ),
+            Self::Error { code, reason } => write!(f, "error {code} {reason}"),
+        }
+    }
+}
+
+/// Answer a request line, `None` if the connection is to be closed.
+///
+/// A request is either a transaction, as a CSV row without header or as a JSON
+/// object, `balance <client>` or `quit`.
+pub fn reply(engine: &SharedEngine, request: &str, amounts: &AmountFormat) -> Option<Reply> {
+    let mut words = request.split_whitespace();
+    match words.next() {
+        // Anything after quit is a malformed request, not a quit.
+        Some("quit") => {
+            return words.next().map(|_| Reply::Error {
+                code: "malformed",
+                reason: "expected quit".to_string(),
+            });
+        }
+        Some("balance") => {
+            let client = match (words.next().map(str::parse::<u16>), words.next()) {
+                (Some(Ok(client)), None) => client,
+                _ => {
+                    return Some(Reply::Error {
+                        code: "malformed",
+                        reason: "expected balance <client>".to_string(),
+                    })
+                }
+            };
+            return Some(match engine.account(client) {
+                Some(account) => Reply::Balance(AccountSnapshot::new(&account, amounts)),
+                None => Reply::Error {
+                    code: "unknown_client",
+                    reason: format!("no account for client {client}"),
+                },
+            });
+        }
+        _ => {}
+    }
+
+    let format = if request.starts_with('{') {
+        InputFormat::JsonLines
+    } else {
+        InputFormat::Csv
+    };
+    let reply = match input::parse_row(request, format) {
+        Ok(tx) => match engine.process_transaction(tx) {
+            Ok(_) => Reply::Accepted,
+            Err(err) => Reply::Rejected(RowErr::Rejected(err)),
+        },
+        Err(err) => Reply::Rejected(RowErr::Input(err)),
+    };
+    Some(reply)
+}
+
+/// Read a line of at most `limit` bytes into `line`, returning the number of bytes
+/// read, 0 at the end of the input. Fails with [`io::ErrorKind::InvalidData`] if
+/// the line is longer.
+pub(crate) fn read_bounded_line<R: BufRead>(
+    reader: &mut R,
+    line: &mut Vec<u8>,
+    limit: u64,
+) -> io::Result<usize> {
+    line.clear();
+    let read = reader.take(limit).read_until(b'\n', line)?;
+    if read as u64 == limit && !line.ends_with(b"\n") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line longer than {limit} bytes"),
+        ));
+    }
+    Ok(read)
+}
+
+/// Read a line like [`read_bounded_line`], returning false at the end of the
+/// input. Fails with [`io::ErrorKind::InvalidData`] as well if the line is not UTF-8.
+pub(crate) fn read_line<R: BufRead>(
+    reader: &mut R,
+    line: &mut String,
+    limit: u64,
+) -> io::Result<bool> {
+    let mut bytes = Vec::new();
+    read_bounded_line(reader, &mut bytes, limit)?;
+    *line =
+        String::from_utf8(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    Ok(!line.is_empty())
+}
+
+/// Answer the request lines read from a connection, one reply line each, until
+/// the connection is closed or `quit` is received. Blank lines are ignored.
+///
+/// A line longer than [`MAX_LINE`] is answered with an error and closes the
+/// connection.
+pub fn handle_connection<R: Read, W: Write>(
+    engine: &SharedEngine,
+    reader: R,
+    writer: W,
+) -> io::Result<()> {
+    let amounts = AmountFormat::default();
+    let mut reader = BufReader::new(reader);
+    let mut writer = BufWriter::new(writer);
+    let mut line = String::new();
+
+    loop {
+        match read_line(&mut reader, &mut line, MAX_LINE) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                let reply = Reply::Error {
+                    code: "malformed",
+                    reason: err.to_string(),
+                };
+                writeln!(writer, "{reply}")?;
+                writer.flush()?;
+                return Err(err);
+            }
+            Err(err) => return Err(err),
+        }
+        let request = line.trim();
+        if request.is_empty() {
+            continue;
+        }
+
+        let Some(reply) = reply(engine, request, &amounts) else {
+            break;
+        };
+        log::debug!("{request} -> {reply}");
+        writeln!(writer, "{reply}")?;
+        writer.flush()?;
+    }
+
+    Ok(())
+}
+
+/// An open connection, counted until it is dropped.
+struct Connection(Arc<AtomicUsize>);
+
+impl Connection {
+    /// Count a new connection, `None` if [`MAX_CONNECTIONS`] are open.
+    fn open(open: &Arc<AtomicUsize>) -> Option<Self> {
+        open.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+            (count < MAX_CONNECTIONS).then_some(count + 1)
+        })
+        .ok()?;
+        Some(Self(open.clone()))
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Answer a connection on its own thread, unless [`MAX_CONNECTIONS`] are open.
+fn spawn_connection<R, W>(
+    engine: &SharedEngine,
+    protocol: Protocol,
+    open: &Arc<AtomicUsize>,
+    peer: String,
+    reader: R,
+    writer: W,
+) where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let Some(connection) = Connection::open(open) else {
+        log::warn!("Closing the connection from {peer}: {MAX_CONNECTIONS} connections are open");
+        return;
+    };
+    let engine = engine.clone();
+    thread::spawn(move || {
+        let _connection = connection;
+        log::info!("Connection from {peer}");
+        if let Err(err) = protocol.handle_connection(&engine, reader, writer) {
+            log::warn!("Connection from {peer} failed: {err}");
+        }
+        log::info!("Connection from {peer} closed");
+    });
+}
+
+/// Accept connections on the TCP listener, answering each on its own thread.
+pub fn serve_tcp(
+    listener: TcpListener,
+    engine: SharedEngine,
+    protocol: Protocol,
+) -> io::Result<()> {
+    let open = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                log::warn!("Failed to accept a connection: {err}");
+                continue;
+            }
+        };
+        let peer = stream
+            .peer_addr()
+            .map_or_else(|_| "unknown peer".to_string(), |addr| addr.to_string());
+        let reader = match stream.try_clone() {
+            Ok(reader) => reader,
+            Err(err) => {
+                log::warn!("Failed to answer the connection from {peer}: {err}");
+                continue;
+            }
+        };
+        spawn_connection(&engine, protocol, &open, peer, reader, stream);
+    }
+    Ok(())
+}
+
+/// Bind a Unix socket listener to the path, replacing the socket left by a server
+/// that stopped. Fails if another server is listening on the socket.
+#[cfg(unix)]
+pub fn bind_unix(
+    path: impl AsRef<std::path::Path>,
+) -> io::Result<std::os::unix::net::UnixListener> {
+    use std::os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    };
+
+    let path = path.as_ref();
+    let stale = std::fs::symlink_metadata(path)
+        .is_ok_and(|metadata| metadata.file_type().is_socket())
+        && UnixStream::connect(path).is_err();
+    if stale {
+        log::info!("Removing the stale socket {}", path.display());
+        std::fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+/// Accept connections on the Unix socket listener, answering each on its own thread.
+#[cfg(unix)]
+pub fn serve_unix(
+    listener: std::os::unix::net::UnixListener,
+    engine: SharedEngine,
+    protocol: Protocol,
+) -> io::Result<()> {
+    let open = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                log::warn!("Failed to accept a connection: {err}");
+                continue;
+            }
+        };
+        let reader = match stream.try_clone() {
+            Ok(reader) => reader,
+            Err(err) => {
+                log::warn!("Failed to answer a connection on the unix socket: {err}");
+                continue;
+            }
+        };
+        spawn_connection(
+            &engine,
+            protocol,
+            &open,
+            "unix socket".to_string(),
+            reader,
+            stream,
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientConfig;
+    use std::net::TcpStream;
+
+    #[test]
+    fn test_handle_connection() {
+        let engine = SharedEngine::new(ClientConfig::default());
+        let requests = "deposit,1,1,2.5\n\
+            {\"type\":\"withdrawal\",\"client\":1,\"tx\":2,\"amount\":\"5.0\"}\n\
+            \n\
+            refund,1,3,1.0\n\
+            balance 1\n\
+            balance 2\n\
+            balance\n\
+            quit now\n\
+            quit\n\
+            deposit,1,4,1.0\n";
+
+        let mut replies = Vec::new();
+        handle_connection(&engine, requests.as_bytes(), &mut replies).unwrap();
+
+        let replies = String::from_utf8(replies).unwrap();
+        let replies: Vec<_> = replies.lines().collect();
+        assert_eq!(replies.len(), 7);
+        assert_eq!(replies[0], "accepted");
+        assert_eq!(
+            replies[1],
+            "rejected insufficient_funds [client 1 tx 2] insufficient funds"
+        );
+        assert!(replies[2].starts_with("rejected unknown_type "));
+        assert_eq!(replies[3], "balance 1 2.5000 0.0000 2.5000 false");
+        assert_eq!(replies[4], "error unknown_client no account for client 2");
+        assert!(replies[5].starts_with("error malformed "));
+        assert_eq!(replies[6], "error malformed expected quit");
+
+        // Nothing is processed after quit.
+        assert!(engine.tx_owner(4).is_none());
+    }
+
+    #[test]
+    fn test_max_connections() {
+        let open = Arc::new(AtomicUsize::new(MAX_CONNECTIONS - 1));
+        let connection = Connection::open(&open).unwrap();
+        assert!(Connection::open(&open).is_none());
+
+        // A closed connection makes room for another.
+        drop(connection);
+        assert_eq!(open.load(Ordering::SeqCst), MAX_CONNECTIONS - 1);
+        assert!(Connection::open(&open).is_some());
+    }
+
+    #[test]
+    fn test_line_too_long() {
+        let engine = SharedEngine::new(ClientConfig::default());
+        let long = "1".repeat(MAX_LINE as usize);
+        let requests = format!("deposit,1,1,1.0\ndeposit,1,2,{long}\ndeposit,1,3,1.0\n");
+
+        let mut replies = Vec::new();
+        let err = handle_connection(&engine, requests.as_bytes(), &mut replies).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let replies = String::from_utf8(replies).unwrap();
+        let replies: Vec<_> = replies.lines().collect();
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0], "accepted");
+        assert!(replies[1].starts_with("error malformed line longer than "));
+        assert!(engine.tx_owner(3).is_none());
+
+        // A line of the maximum length is read.
+        let mut line = String::new();
+        let request = format!("{}\n", &long[1..]);
+        read_line(&mut request.as_bytes(), &mut line, MAX_LINE).unwrap();
+        assert_eq!(line.len() as u64, MAX_LINE);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_bind_unix() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tx.sock");
+
+        let listener = bind_unix(&path).unwrap();
+        // The socket of a running server is kept.
+        assert_eq!(
+            bind_unix(&path).unwrap_err().kind(),
+            io::ErrorKind::AddrInUse
+        );
+
+        // The socket of a stopped server is replaced.
+        drop(listener);
+        assert!(path.exists());
+        let listener = bind_unix(&path).unwrap();
+        let engine = SharedEngine::new(ClientConfig::default());
+        thread::spawn(move || serve_unix(listener, engine, Protocol::Lines));
+
+        let mut stream = std::os::unix::net::UnixStream::connect(&path).unwrap();
+        writeln!(stream, "deposit,1,1,1.0\nquit").unwrap();
+        let mut replies = BufReader::new(stream).lines();
+        assert_eq!(replies.next().unwrap().unwrap(), "accepted");
+    }
+
+    #[test]
+    fn test_serve_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let engine = SharedEngine::new(ClientConfig::default());
+        thread::spawn(move || serve_tcp(listener, engine, Protocol::Lines));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut replies = BufReader::new(stream.try_clone().unwrap()).lines();
+
+        writeln!(stream, "deposit,7,1,1.0").unwrap();
+        assert_eq!(replies.next().unwrap().unwrap(), "accepted");
+
+        // A second connection shares the engine.
+        let mut other = TcpStream::connect(addr).unwrap();
+        writeln!(other, "balance 7\nquit").unwrap();
+        let mut lines = BufReader::new(other).lines();
+        assert_eq!(
+            lines.next().unwrap().unwrap(),
+            "balance 7 1.0000 0.0000 1.0000 false"
+        );
+        assert!(lines.next().is_none());
+
+        writeln!(stream, "quit").unwrap();
+        assert!(replies.next().is_none());
+    }
+}
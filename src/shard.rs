@@ -0,0 +1,487 @@
+use crate::{
+    client::{BalanceDelta, ClientAccount, ClientConfig},
+    engine::{owner_mismatch, registers_id},
+    error::EngineErr,
+    snapshot::EngineSnapshot,
+    storage::StorageErr,
+    types::TransactionType,
+    PaymentEngine,
+};
+
+use std::{
+    any::Any,
+    collections::HashMap,
+    panic::{self, AssertUnwindSafe},
+    sync::mpsc::{self, Receiver, Sender},
+    thread::{self, JoinHandle},
+};
+
+/// The outcome of a transaction, as returned by [`PaymentEngine::process_transaction`].
+pub type Outcome = Result<BalanceDelta, EngineErr>;
+
+/// A request to the worker thread of a shard.
+enum Request {
+    /// Process the transactions at the given positions of the batch.
+    Process(Vec<(usize, TransactionType)>),
+    /// Reply with the owner of the transaction ID.
+    TxOwner(u32, Sender<Reply<Result<Option<u16>, StorageErr>>>),
+    /// Reply with the accounts of the shard.
+    Accounts(Sender<Reply<Result<Vec<ClientAccount>, StorageErr>>>),
+}
+
+/// The reply of a worker thread to a request.
+enum Reply<T> {
+    /// The request was handled. The outcomes of a batch are the outcomes of the
+    /// transactions at the given positions of the batch.
+    Done(T),
+    /// The worker panicked, e.g. on a violated invariant.
+    Panic(Box<dyn Any + Send>),
+}
+
+impl<T> Reply<T> {
+    /// Handle a request, replying with the panic if it panics. Returns false if
+    /// it panicked.
+    fn send(reply: &Sender<Self>, f: impl FnOnce() -> T) -> bool {
+        let (reply_with, handled) = match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(value) => (Self::Done(value), true),
+            Err(payload) => (Self::Panic(payload), false),
+        };
+        // Replies fail only if the sharded engine is gone.
+        let _ = reply.send(reply_with);
+        handled
+    }
+}
+
+/// The outcomes of the transactions at the given positions of a batch.
+type Outcomes = Vec<(usize, Outcome)>;
+
+/// A worker thread owning the accounts of one shard.
+struct Shard {
+    requests: Sender<Request>,
+    handle: JoinHandle<()>,
+}
+
+impl Shard {
+    fn spawn(
+        accounts: Vec<ClientAccount>,
+        config: ClientConfig,
+        replies: Sender<Reply<Outcomes>>,
+    ) -> Result<Self, StorageErr> {
+        let mut engine = PaymentEngine::with_config(config);
+        engine.restore(EngineSnapshot::new(accounts))?;
+
+        let (requests, receiver) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            for request in receiver {
+                let handled = match request {
+                    Request::Process(txs) => Reply::send(&replies, || {
+                        txs.into_iter()
+                            .map(|(pos, tx)| (pos, engine.process_transaction(tx)))
+                            .collect()
+                    }),
+                    Request::TxOwner(tx, reply) => Reply::send(&reply, || engine.tx_owner(tx)),
+                    Request::Accounts(reply) => Reply::send(&reply, || {
+                        engine.snapshot().map(|snapshot| snapshot.accounts)
+                    }),
+                };
+                // The state of the shard is unknown after a panic.
+                if !handled {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self { requests, handle })
+    }
+}
+
+/// Processes transactions on worker threads, each owning the accounts of the
+/// clients routed to it by client ID.
+///
+/// Transactions of a client are processed in order, and the outcomes and the
+/// final state are identical to those of a sequential [`PaymentEngine`].
+/// Transactions referencing an ID seen for another client wait for all shards
+/// to catch up, so that the engine-wide ownership of transaction IDs is
+/// checked in input order.
+pub struct ShardedEngine {
+    shards: Vec<Shard>,
+    replies: Receiver<Reply<Outcomes>>,
+    /// The client last routed a deposit or withdrawal with the ID, the only
+    /// client that may own it. Other clients referencing the ID are checked
+    /// against all shards.
+    claims: HashMap<u32, u16>,
+}
+
+impl ShardedEngine {
+    /// Constructs a new [`ShardedEngine`] with the given number of shards, creating
+    /// accounts with the given configuration.
+    pub fn new(shards: usize, config: ClientConfig) -> Self {
+        Self::from_snapshot(EngineSnapshot::new(Vec::new()), shards, config)
+            .expect("restoring no accounts never fails")
+    }
+
+    /// Constructs a new [`ShardedEngine`] with the given number of shards, starting
+    /// from the accounts of the snapshot.
+    pub fn from_snapshot(
+        snapshot: EngineSnapshot,
+        shards: usize,
+        config: ClientConfig,
+    ) -> Result<Self, StorageErr> {
+        snapshot.validate()?;
+        let shards = shards.max(1);
+        let mut claims = HashMap::new();
+        let mut partitions = vec![Vec::new(); shards];
+        for account in snapshot.accounts {
+            for (tx, _) in account.transactions() {
+                claims.insert(tx.transaction_id(), account.client());
+            }
+            partitions[account.client() as usize % shards].push(account);
+        }
+
+        let (sender, replies) = mpsc::channel();
+        let shards = partitions
+            .into_iter()
+            .map(|accounts| Shard::spawn(accounts, config, sender.clone()))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            shards,
+            replies,
+            claims,
+        })
+    }
+
+    /// The number of shards.
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Process the transactions, returning their outcomes in order.
+    pub fn process_batch(
+        &mut self,
+        txs: impl IntoIterator<Item = TransactionType>,
+    ) -> Result<Vec<Outcome>, StorageErr> {
+        let mut outcomes = Vec::new();
+        // Transactions are sent to the shards in chunks, at the latest before
+        // checking the owner of a transaction ID.
+        let mut routed = vec![Vec::new(); self.shards.len()];
+        let mut sent = 0;
+
+        for tx in txs {
+            let pos = outcomes.len();
+            outcomes.push(None);
+
+            let client = tx.client_id();
+            let tx_id = tx.transaction_id();
+            if self
+                .claims
+                .get(&tx_id)
+                .is_some_and(|&claimant| claimant != client)
+            {
+                sent += self.dispatch(&mut routed);
+                if let Some(owner) = self.tx_owner(tx_id)?.filter(|&owner| owner != client) {
+                    self.claims.insert(tx_id, owner);
+                    outcomes[pos] = Some(Err(owner_mismatch(&tx, owner)));
+                    continue;
+                }
+            }
+            if registers_id(&tx) {
+                self.claims.insert(tx_id, client);
+            }
+
+            routed[client as usize % self.shards.len()].push((pos, tx));
+        }
+        sent += self.dispatch(&mut routed);
+
+        for _ in 0..sent {
+            for (pos, outcome) in self.recv(&self.replies) {
+                outcomes[pos] = Some(outcome);
+            }
+        }
+
+        Ok(outcomes
+            .into_iter()
+            .map(|outcome| outcome.expect("every transaction has an outcome"))
+            .collect())
+    }
+
+    /// A snapshot of the accounts of all shards.
+    pub fn snapshot(&self) -> Result<EngineSnapshot, StorageErr> {
+        let replies: Vec<_> = self
+            .shards
+            .iter()
+            .map(|shard| {
+                let (reply, receiver) = mpsc::channel();
+                self.send(shard, Request::Accounts(reply));
+                receiver
+            })
+            .collect();
+
+        let mut accounts = Vec::new();
+        for reply in replies {
+            accounts.extend(self.recv(&reply)?);
+        }
+        Ok(EngineSnapshot::new(accounts))
+    }
+
+    /// Send the routed transactions to their shards, returning the number of
+    /// requests sent.
+    fn dispatch(&self, routed: &mut [Vec<(usize, TransactionType)>]) -> usize {
+        let mut sent = 0;
+        for (shard, txs) in self.shards.iter().zip(routed) {
+            if !txs.is_empty() {
+                self.send(shard, Request::Process(std::mem::take(txs)));
+                sent += 1;
+            }
+        }
+        sent
+    }
+
+    /// The owner of the transaction ID, once all shards processed the
+    /// transactions sent before.
+    fn tx_owner(&self, tx: u32) -> Result<Option<u16>, StorageErr> {
+        let replies: Vec<_> = self
+            .shards
+            .iter()
+            .map(|shard| {
+                let (reply, receiver) = mpsc::channel();
+                self.send(shard, Request::TxOwner(tx, reply));
+                receiver
+            })
+            .collect();
+
+        let mut owner = None;
+        for reply in replies {
+            owner = owner.or(self.recv(&reply)?);
+        }
+        Ok(owner)
+    }
+
+    fn send(&self, shard: &Shard, request: Request) {
+        if shard.requests.send(request).is_err() {
+            self.resume_panic();
+        }
+    }
+
+    /// Receive the reply to a request, resuming the panic of the worker.
+    fn recv<T>(&self, receiver: &Receiver<Reply<T>>) -> T {
+        match receiver.recv() {
+            Ok(Reply::Done(value)) => value,
+            Ok(Reply::Panic(payload)) => panic::resume_unwind(payload),
+            Err(_) => self.resume_panic(),
+        }
+    }
+
+    /// Resume the panic of a worker that stopped, which it replied to a batch
+    /// not received yet.
+    fn resume_panic(&self) -> ! {
+        while let Ok(reply) = self.replies.try_recv() {
+            if let Reply::Panic(payload) = reply {
+                panic::resume_unwind(payload);
+            }
+        }
+        panic!("shard worker panicked");
+    }
+}
+
+impl Drop for ShardedEngine {
+    fn drop(&mut self) {
+        for shard in self.shards.drain(..) {
+            drop(shard.requests);
+            if shard.handle.join().is_err() {
+                log::error!("Shard worker panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::EngineErrKind, ClientErr};
+    use rust_decimal::Decimal;
+
+    fn workload() -> Vec<TransactionType> {
+        let mut txs = Vec::new();
+        for i in 0..2000u32 {
+            let client = (i % 13) as u16;
+            let amount = Decimal::new((i % 7 + 1) as i64 * 10, 1);
+            txs.push(match i % 5 {
+                0 | 1 => TransactionType::Deposit {
+                    client,
+                    tx: i,
+                    amount,
+                },
+                2 => TransactionType::Withdrawal {
+                    client,
+                    tx: i,
+                    amount,
+                },
+                3 => TransactionType::Dispute { client, tx: i - 3 },
+                _ => TransactionType::Resolve { client, tx: i - 4 },
+            });
+        }
+        txs
+    }
+
+    fn sequential(txs: &[TransactionType]) -> (Vec<Outcome>, EngineSnapshot) {
+        let mut engine = PaymentEngine::new();
+        let outcomes = txs
+            .iter()
+            .map(|tx| engine.process_transaction(tx.clone()))
+            .collect();
+        (outcomes, engine.snapshot().unwrap())
+    }
+
+    fn codes(outcomes: &[Outcome]) -> Vec<Option<&'static str>> {
+        outcomes
+            .iter()
+            .map(|outcome| outcome.as_ref().err().map(EngineErr::code))
+            .collect()
+    }
+
+    #[test]
+    fn test_identical_to_sequential() {
+        let txs = workload();
+        let (expected, snapshot) = sequential(&txs);
+
+        let mut engine = ShardedEngine::new(4, ClientConfig::default());
+        let mut outcomes = Vec::new();
+        for batch in txs.chunks(300) {
+            outcomes.extend(engine.process_batch(batch.to_vec()).unwrap());
+        }
+
+        assert_eq!(codes(&outcomes), codes(&expected));
+        assert_eq!(
+            outcomes.iter().flatten().collect::<Vec<_>>(),
+            expected.iter().flatten().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            serde_json::to_value(engine.snapshot().unwrap().accounts).unwrap(),
+            serde_json::to_value(snapshot.accounts).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_transaction_ids_across_shards() {
+        let deposit = |client, tx| TransactionType::Deposit {
+            client,
+            tx,
+            amount: Decimal::ONE,
+        };
+        let txs = vec![
+            // Rejected, so the ID remains free for client 2.
+            TransactionType::Withdrawal {
+                client: 1,
+                tx: 1,
+                amount: Decimal::ONE,
+            },
+            deposit(2, 1),
+            deposit(1, 1),
+            TransactionType::Dispute { client: 1, tx: 1 },
+            TransactionType::Dispute { client: 2, tx: 1 },
+            deposit(3, 2),
+            deposit(1, 2),
+        ];
+        let (expected, _) = sequential(&txs);
+
+        let mut engine = ShardedEngine::new(2, ClientConfig::default());
+        let outcomes = engine.process_batch(txs).unwrap();
+        assert_eq!(codes(&outcomes), codes(&expected));
+        assert!(matches!(
+            outcomes[0].as_ref().unwrap_err().kind(),
+            EngineErrKind::Client(ClientErr::InsufficientFunds)
+        ));
+        assert!(matches!(
+            outcomes[2].as_ref().unwrap_err().kind(),
+            EngineErrKind::TransactionIdInUse { owner: 2 }
+        ));
+        assert!(matches!(
+            outcomes[3].as_ref().unwrap_err().kind(),
+            EngineErrKind::TransactionOwnerMismatch { owner: 2 }
+        ));
+        assert!(outcomes[4].is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "Addition overflowed")]
+    fn test_worker_panic() {
+        let mut engine = ShardedEngine::new(2, ClientConfig::default());
+        // The second deposit overflows the available funds.
+        let _ = engine.process_batch([
+            TransactionType::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Decimal::MAX,
+            },
+            TransactionType::Deposit {
+                client: 1,
+                tx: 2,
+                amount: Decimal::MAX,
+            },
+        ]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Addition overflowed")]
+    fn test_worker_panic_tx_owner() {
+        // The owner of the ID is asked from the shard that panicked on the deposit.
+        let mut engine = ShardedEngine::new(2, ClientConfig::default());
+        let _ = engine.process_batch([
+            TransactionType::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Decimal::MAX,
+            },
+            TransactionType::Deposit {
+                client: 1,
+                tx: 2,
+                amount: Decimal::MAX,
+            },
+            TransactionType::Dispute { client: 2, tx: 2 },
+        ]);
+    }
+
+    #[test]
+    fn test_invariant_abort() {
+        let config = ClientConfig {
+            invariants: crate::invariant::InvariantCheck::Abort,
+            ..Default::default()
+        };
+        let mut account = ClientAccount::new(1);
+        // Corrupt the account behind the back of the books.
+        account.held = -Decimal::ONE;
+        let snapshot = EngineSnapshot::new(vec![account]);
+
+        // The violation is a rejection, and the worker keeps processing.
+        let mut engine = ShardedEngine::from_snapshot(snapshot, 2, config).unwrap();
+        let outcomes = engine
+            .process_batch([
+                TransactionType::Deposit {
+                    client: 1,
+                    tx: 1,
+                    amount: Decimal::ONE,
+                },
+                TransactionType::Deposit {
+                    client: 3,
+                    tx: 2,
+                    amount: Decimal::ONE,
+                },
+            ])
+            .unwrap();
+        assert_eq!(codes(&outcomes), [Some("invariant_violated"), None]);
+    }
+
+    #[test]
+    fn test_from_snapshot() {
+        let txs = workload();
+        let (half, rest) = txs.split_at(1000);
+        let (_, snapshot) = sequential(half);
+        let (expected, _) = sequential(&txs);
+
+        let mut engine =
+            ShardedEngine::from_snapshot(snapshot, 3, ClientConfig::default()).unwrap();
+        let outcomes = engine.process_batch(rest.to_vec()).unwrap();
+        assert_eq!(codes(&outcomes), codes(&expected[1000..]));
+    }
+}
@@ -0,0 +1,416 @@
+use crate::{
+    client::{BalanceDelta, ClientAccount, ClientConfig, DisputeState},
+    engine::{owner_mismatch, registers_id},
+    error::EngineErr,
+    journal::{Journal, JournalEntry, JournalRecord},
+    snapshot::EngineSnapshot,
+    storage::StorageErr,
+    types::TransactionType,
+};
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard},
+};
+
+/// Number of independently locked parts of the transaction ID registry.
+const REGISTRY_STRIPES: usize = 64;
+
+/// A handle to an engine shared between threads, with every account locked
+/// independently.
+///
+/// Transactions of different clients are processed concurrently. A deposit or
+/// withdrawal locks the part of the transaction ID registry holding its ID until
+/// it is registered, so that concurrent transactions of different clients cannot
+/// claim the same ID. Reads return copies of the accounts, consistent at the
+/// time of the read while writes continue.
+///
+/// Accepted transactions can be appended to a [`Journal`], which serializes
+/// them: the journal is locked while an accepted transaction is appended, with
+/// its account and ID still locked, so that the journal replays in order. A
+/// transaction that cannot be journaled is not applied.
+///
+/// Cloning the handle is cheap and refers to the same engine.
+#[derive(Clone)]
+pub struct SharedEngine {
+    inner: Arc<Inner>,
+}
+
+/// The independently locked accounts, by client ID.
+type Accounts = HashMap<u16, Arc<Mutex<ClientAccount>>>;
+
+struct Inner {
+    accounts: RwLock<Accounts>,
+    /// The client owning every registered transaction ID, striped by ID.
+    registry: Vec<Mutex<HashMap<u32, u16>>>,
+    /// Configuration applied to every account.
+    config: ClientConfig,
+    journal: Option<Mutex<Journaled>>,
+}
+
+/// The journal of the accepted transactions and the number of its entries.
+struct Journaled {
+    journal: Journal,
+    entries: u64,
+}
+
+/// Lock the mutex. A poisoned lock means a transaction panicked, e.g. on a
+/// violated invariant, and the state cannot be trusted anymore.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().expect("engine lock poisoned")
+}
+
+impl SharedEngine {
+    /// Constructs a new [`SharedEngine`] creating accounts with the given configuration.
+    pub fn new(config: ClientConfig) -> Self {
+        Self::from_snapshot(EngineSnapshot::new(Vec::new()), config)
+            .expect("restoring no accounts never fails")
+    }
+
+    /// Constructs a new [`SharedEngine`] starting from the accounts of the snapshot.
+    pub fn from_snapshot(
+        snapshot: EngineSnapshot,
+        config: ClientConfig,
+    ) -> Result<Self, StorageErr> {
+        Self::build(snapshot, config, None)
+    }
+
+    /// Constructs a new [`SharedEngine`] starting from the accounts of the snapshot,
+    /// appending the accepted transactions to the journal, which already has the
+    /// given number of entries.
+    ///
+    /// The entries are recorded with the source `serve` and their position in the
+    /// journal as line.
+    pub fn with_journal(
+        snapshot: EngineSnapshot,
+        config: ClientConfig,
+        journal: Journal,
+        entries: u64,
+    ) -> Result<Self, StorageErr> {
+        Self::build(snapshot, config, Some(Journaled { journal, entries }))
+    }
+
+    fn build(
+        snapshot: EngineSnapshot,
+        config: ClientConfig,
+        journal: Option<Journaled>,
+    ) -> Result<Self, StorageErr> {
+        snapshot.validate()?;
+        let mut registry: Vec<HashMap<u32, u16>> = vec![HashMap::new(); REGISTRY_STRIPES];
+        let mut accounts = HashMap::new();
+        for mut account in snapshot.accounts {
+            for (tx, _) in account.transactions() {
+                let tx = tx.transaction_id();
+                registry[tx as usize % REGISTRY_STRIPES].insert(tx, account.client());
+            }
+            account.config = config;
+            accounts.insert(account.client(), Arc::new(Mutex::new(account)));
+        }
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                accounts: RwLock::new(accounts),
+                registry: registry.into_iter().map(Mutex::new).collect(),
+                config,
+                journal: journal.map(Mutex::new),
+            }),
+        })
+    }
+
+    /// Process the given transaction, returning the change of the balances of the account.
+    pub fn process_transaction(&self, tx: TransactionType) -> Result<BalanceDelta, EngineErr> {
+        let client_id = tx.client_id();
+        let tx_id = tx.transaction_id();
+
+        let registry = lock(self.registry(tx_id));
+        if let Some(&owner) = registry.get(&tx_id) {
+            if owner != client_id {
+                return Err(owner_mismatch(&tx, owner));
+            }
+        }
+        // Only a deposit or withdrawal keeps the registry locked until its ID is
+        // registered.
+        let mut registry = registers_id(&tx).then_some(registry);
+
+        let account = self.account_lock(client_id);
+        let mut account = lock(&account);
+        let Some(journal) = &self.inner.journal else {
+            let delta = account
+                .process_transaction(tx)
+                .map_err(|err| EngineErr::new(client_id, tx_id, err))?;
+            if let Some(registry) = &mut registry {
+                registry.insert(tx_id, client_id);
+            }
+            return Ok(delta);
+        };
+
+        // A journaled transaction is applied to a copy of the account, which
+        // replaces it once the transaction is journaled. The account and the ID stay
+        // locked until then.
+        let mut updated = account.clone();
+        let delta = updated
+            .process_transaction(tx.clone())
+            .map_err(|err| EngineErr::new(client_id, tx_id, err))?;
+        let mut journal = lock(journal);
+        let entry = JournalEntry {
+            source: "serve".to_string(),
+            line: journal.entries + 1,
+            transaction: tx,
+            delta,
+        };
+        if let Err(err) = journal.journal.append(&JournalRecord::Applied(entry)) {
+            log::error!("Failed to journal transaction {tx_id}: {err}");
+            return Err(EngineErr::new(client_id, tx_id, err));
+        }
+        journal.entries += 1;
+
+        *account = updated;
+        if let Some(registry) = &mut registry {
+            registry.insert(tx_id, client_id);
+        }
+        Ok(delta)
+    }
+
+    /// A copy of the account of the given client, if any transaction was processed for it.
+    pub fn account(&self, client: u16) -> Option<ClientAccount> {
+        let account = self.read_accounts().get(&client).cloned()?;
+        let account = lock(&account).clone();
+        Some(account)
+    }
+
+    /// The client owning the given transaction ID, if a deposit or withdrawal with it
+    /// was processed.
+    pub fn tx_owner(&self, tx: u32) -> Option<u16> {
+        lock(self.registry(tx)).get(&tx).copied()
+    }
+
+    /// The dispute state of the given transaction, if it was processed.
+    pub fn dispute_state(&self, tx: u32) -> Option<DisputeState> {
+        let client = self.tx_owner(tx)?;
+        let account = self.read_accounts().get(&client).cloned()?;
+        let state = lock(&account).dispute_state(tx);
+        state
+    }
+
+    /// A snapshot of all accounts at a single point in time.
+    ///
+    /// New accounts are not created and all accounts are locked while they are
+    /// copied, so no transaction is seen by one account and missed by another.
+    pub fn snapshot(&self) -> EngineSnapshot {
+        let accounts = self.read_accounts();
+        let mut clients: Vec<_> = accounts.keys().copied().collect();
+        // Accounts are locked in the order of the client IDs.
+        clients.sort_unstable();
+
+        let guards: Vec<_> = clients
+            .iter()
+            .map(|client| lock(&accounts[client]))
+            .collect();
+        EngineSnapshot::new(guards.iter().map(|account| (**account).clone()).collect())
+    }
+
+    fn registry(&self, tx: u32) -> &Mutex<HashMap<u32, u16>> {
+        &self.inner.registry[tx as usize % REGISTRY_STRIPES]
+    }
+
+    fn read_accounts(&self) -> RwLockReadGuard<'_, Accounts> {
+        self.inner.accounts.read().expect("engine lock poisoned")
+    }
+
+    /// The lock of the account of the given client, creating the account if missing.
+    fn account_lock(&self, client: u16) -> Arc<Mutex<ClientAccount>> {
+        if let Some(account) = self.read_accounts().get(&client) {
+            return Arc::clone(account);
+        }
+
+        let mut accounts = self.inner.accounts.write().expect("engine lock poisoned");
+        let account = accounts.entry(client).or_insert_with(|| {
+            Arc::new(Mutex::new(ClientAccount::with_config(
+                client,
+                self.inner.config,
+            )))
+        });
+        Arc::clone(account)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::EngineErrKind;
+    use rust_decimal::Decimal;
+    use std::thread;
+
+    fn deposit(client: u16, tx: u32) -> TransactionType {
+        TransactionType::Deposit {
+            client,
+            tx,
+            amount: Decimal::ONE,
+        }
+    }
+
+    #[test]
+    fn test_send_sync() {
+        fn check<T: Send + Sync>() {}
+        check::<SharedEngine>();
+    }
+
+    #[test]
+    fn test_concurrent_deposits() {
+        let engine = SharedEngine::new(ClientConfig::default());
+
+        thread::scope(|scope| {
+            for thread in 0..8u32 {
+                let engine = engine.clone();
+                scope.spawn(move || {
+                    for i in 0..500 {
+                        let client = (i % 10) as u16;
+                        engine
+                            .process_transaction(deposit(client, thread * 1000 + i))
+                            .unwrap();
+                    }
+                });
+            }
+        });
+
+        let snapshot = engine.snapshot();
+        assert_eq!(snapshot.accounts.len(), 10);
+        for account in &snapshot.accounts {
+            assert_eq!(account.total(), Decimal::from(400));
+        }
+        assert_eq!(engine.tx_owner(7005), Some(5));
+        assert_eq!(engine.dispute_state(7005), Some(DisputeState::Processed));
+    }
+
+    #[test]
+    fn test_journal() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.jsonl");
+        let journal = Journal::open(&path, 1).unwrap();
+        let engine = SharedEngine::with_journal(
+            EngineSnapshot::new(Vec::new()),
+            ClientConfig::default(),
+            journal,
+            0,
+        )
+        .unwrap();
+
+        thread::scope(|scope| {
+            for thread in 0..4u32 {
+                let engine = engine.clone();
+                scope.spawn(move || {
+                    for i in 0..100 {
+                        let client = (i % 3) as u16;
+                        let tx = thread * 1000 + i;
+                        engine.process_transaction(deposit(client, tx)).unwrap();
+                        // Withdrawals of a client race the deposits of other threads.
+                        let _ = engine.process_transaction(TransactionType::Withdrawal {
+                            client,
+                            tx: tx + 500,
+                            amount: Decimal::from(2),
+                        });
+                    }
+                });
+            }
+        });
+        // Rejected transactions are not journaled.
+        engine.process_transaction(deposit(1, 0)).unwrap_err();
+
+        // The journal replays to the same state.
+        let records = Journal::recover(&path).unwrap();
+        let entries: Vec<_> = records
+            .iter()
+            .map(|record| match record {
+                JournalRecord::Applied(entry) => entry,
+                record => panic!("unexpected {record:?}"),
+            })
+            .collect();
+        assert_eq!(entries.last().unwrap().line, entries.len() as u64);
+
+        let mut replayed = crate::PaymentEngine::new();
+        replayed.replay_journal(entries).unwrap();
+        assert_eq!(
+            serde_json::to_value(replayed.snapshot().unwrap()).unwrap(),
+            serde_json::to_value(engine.snapshot()).unwrap()
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_journal_failure() {
+        // Every write to /dev/full fails.
+        let journal = Journal::open("/dev/full", 1).unwrap();
+        let engine = SharedEngine::with_journal(
+            EngineSnapshot::new(Vec::new()),
+            ClientConfig::default(),
+            journal,
+            0,
+        )
+        .unwrap();
+
+        // A transaction that cannot be journaled is not applied.
+        engine.process_transaction(deposit(1, 1)).unwrap_err();
+        assert_eq!(engine.tx_owner(1), None);
+        assert_eq!(engine.account(1).unwrap().total, Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_concurrent_transaction_id_claims() {
+        let engine = SharedEngine::new(ClientConfig::default());
+
+        let accepted: usize = thread::scope(|scope| {
+            let handles: Vec<_> = (0..8u16)
+                .map(|client| {
+                    let engine = engine.clone();
+                    scope.spawn(move || engine.process_transaction(deposit(client, 1)))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| match handle.join().unwrap() {
+                    Ok(_) => 1,
+                    Err(err) => {
+                        assert!(matches!(
+                            err.kind(),
+                            EngineErrKind::TransactionIdInUse { .. }
+                        ));
+                        0
+                    }
+                })
+                .sum()
+        });
+
+        assert_eq!(accepted, 1);
+        let owner = engine.tx_owner(1).unwrap();
+        assert_eq!(engine.account(owner).unwrap().total(), Decimal::ONE);
+    }
+
+    #[test]
+    fn test_consistent_snapshot() {
+        let engine = SharedEngine::new(ClientConfig::default());
+        engine.process_transaction(deposit(1, 0)).unwrap();
+        engine.process_transaction(deposit(2, 1)).unwrap();
+
+        thread::scope(|scope| {
+            let writer = engine.clone();
+            scope.spawn(move || {
+                // Client 1 is always credited before client 2.
+                for tx in 1..1000 {
+                    writer.process_transaction(deposit(1, tx * 2)).unwrap();
+                    writer.process_transaction(deposit(2, tx * 2 + 1)).unwrap();
+                }
+            });
+
+            for _ in 0..100 {
+                let snapshot = engine.snapshot();
+                let [first, second] = &snapshot.accounts[..] else {
+                    panic!("two accounts expected");
+                };
+                let difference = first.total() - second.total();
+                assert!(difference == Decimal::ZERO || difference == Decimal::ONE);
+            }
+        });
+
+        assert_eq!(engine.account(2).unwrap().total(), Decimal::from(1000));
+    }
+}
@@ -0,0 +1,113 @@
+use crate::{client::ClientAccount, storage::StorageErr};
+
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Read, Write},
+};
+
+/// The complete state of a [`PaymentEngine`](crate::engine::PaymentEngine):
+/// balances, locks, transaction history and dispute states of every account.
+///
+/// The owners of the transaction IDs are not stored, they are derived from
+/// the transaction history of the accounts when the snapshot is restored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineSnapshot {
+    version: u32,
+    /// The accounts, ordered by client ID.
+    pub accounts: Vec<ClientAccount>,
+}
+
+impl EngineSnapshot {
+    /// Version of the snapshot format written by this engine. Version 2 records the
+    /// ledger and the entries of every account.
+    pub const VERSION: u32 = 2;
+
+    /// Constructs a new [`EngineSnapshot`] of the given accounts.
+    pub fn new(mut accounts: Vec<ClientAccount>) -> Self {
+        accounts.sort_by_key(ClientAccount::client);
+        Self {
+            version: Self::VERSION,
+            accounts,
+        }
+    }
+
+    /// Read a snapshot written by [`EngineSnapshot::write`], failing if it is not
+    /// [valid](EngineSnapshot::validate).
+    pub fn read<R: Read>(reader: R) -> Result<Self, StorageErr> {
+        let snapshot = Self::read_unchecked(reader)?;
+        snapshot.validate()?;
+        Ok(snapshot)
+    }
+
+    /// Read a snapshot without validating it, to audit a snapshot that cannot be
+    /// restored.
+    pub fn read_unchecked<R: Read>(mut reader: R) -> Result<Self, StorageErr> {
+        /// The accounts are decoded once the version is known to be supported.
+        #[derive(Deserialize)]
+        struct Versioned<'a> {
+            version: u32,
+            #[serde(borrow)]
+            accounts: &'a RawValue,
+        }
+
+        let mut json = String::new();
+        reader.read_to_string(&mut json)?;
+        let snapshot: Versioned = serde_json::from_str(&json)?;
+        if snapshot.version != Self::VERSION {
+            return Err(StorageErr::Corrupt(format!(
+                "unsupported snapshot version {}",
+                snapshot.version
+            )));
+        }
+        Ok(Self {
+            version: snapshot.version,
+            accounts: serde_json::from_str(snapshot.accounts.get())?,
+        })
+    }
+
+    /// Check that the snapshot can be restored: every client has a single account,
+    /// every processed transaction has a dispute state and the other way around,
+    /// and no transaction ID is used by several clients.
+    pub fn validate(&self) -> Result<(), StorageErr> {
+        let mut clients = HashSet::new();
+        let mut owners = HashMap::new();
+
+        for account in &self.accounts {
+            let client = account.client();
+            if !clients.insert(client) {
+                return Err(StorageErr::Corrupt(format!(
+                    "several accounts of client {client}"
+                )));
+            }
+            if let Some(tx) = account.missing_dispute_states().first() {
+                return Err(StorageErr::Corrupt(format!(
+                    "[client {client}] transaction {tx} has no dispute state"
+                )));
+            }
+            if let Some(tx) = account.orphaned_dispute_states().first() {
+                return Err(StorageErr::Corrupt(format!(
+                    "[client {client}] dispute state of unknown transaction {tx}"
+                )));
+            }
+            for (tx, _) in account.transactions() {
+                let tx = tx.transaction_id();
+                if let Some(owner) = owners.insert(tx, client) {
+                    return Err(StorageErr::Corrupt(format!(
+                        "transaction {tx} used by clients {owner} and {client}"
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the snapshot as JSON.
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), StorageErr> {
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
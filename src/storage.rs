@@ -0,0 +1,519 @@
+use crate::client::ClientAccount;
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeSet, HashMap},
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// Reasons the state of the engine cannot be loaded or stored.
+#[derive(Debug)]
+pub enum StorageErr {
+    /// Reading or writing the underlying files failed.
+    Io(std::io::Error),
+    /// The stored state could not be decoded.
+    Corrupt(String),
+}
+
+impl StorageErr {
+    /// A stable, machine-readable code of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Io(_) => "storage_io",
+            Self::Corrupt(_) => "storage_corrupt",
+        }
+    }
+}
+
+impl fmt::Display for StorageErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "storage i/o error: {err}"),
+            Self::Corrupt(reason) => write!(f, "corrupt storage: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for StorageErr {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Corrupt(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for StorageErr {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for StorageErr {
+    fn from(err: serde_json::Error) -> Self {
+        if err.is_io() {
+            Self::Io(err.into())
+        } else {
+            Self::Corrupt(err.to_string())
+        }
+    }
+}
+
+/// Iterator over the accounts of a [`Storage`].
+pub type Accounts<'a> = Box<dyn Iterator<Item = Result<Cow<'a, ClientAccount>, StorageErr>> + 'a>;
+
+/// Where the [`PaymentEngine`](crate::engine::PaymentEngine) keeps the client
+/// accounts and the owners of the processed transaction IDs.
+pub trait Storage {
+    /// The account of the given client, if it was stored.
+    fn account(&self, client: u16) -> Result<Option<Cow<'_, ClientAccount>>, StorageErr>;
+
+    /// Remove the account of the given client to update it. The account is
+    /// handed back with [`Storage::put_account`].
+    fn take_account(&mut self, client: u16) -> Result<Option<ClientAccount>, StorageErr>;
+
+    /// Store the account, replacing its previous state.
+    fn put_account(&mut self, account: ClientAccount) -> Result<(), StorageErr>;
+
+    /// Iterate over all accounts, in no particular order.
+    fn accounts(&self) -> Accounts<'_>;
+
+    /// The client owning the given deposit or withdrawal, if it was processed.
+    fn tx_owner(&self, tx: u32) -> Result<Option<u16>, StorageErr>;
+
+    /// Record the client owning the given deposit or withdrawal.
+    fn register_tx(&mut self, tx: u32, client: u16) -> Result<(), StorageErr>;
+
+    /// Forget the client owning the given deposit or withdrawal.
+    fn unregister_tx(&mut self, tx: u32) -> Result<(), StorageErr>;
+
+    /// Persist all pending changes.
+    fn flush(&mut self) -> Result<(), StorageErr> {
+        Ok(())
+    }
+}
+
+impl<S: Storage + ?Sized> Storage for Box<S> {
+    fn account(&self, client: u16) -> Result<Option<Cow<'_, ClientAccount>>, StorageErr> {
+        (**self).account(client)
+    }
+
+    fn take_account(&mut self, client: u16) -> Result<Option<ClientAccount>, StorageErr> {
+        (**self).take_account(client)
+    }
+
+    fn put_account(&mut self, account: ClientAccount) -> Result<(), StorageErr> {
+        (**self).put_account(account)
+    }
+
+    fn accounts(&self) -> Accounts<'_> {
+        (**self).accounts()
+    }
+
+    fn tx_owner(&self, tx: u32) -> Result<Option<u16>, StorageErr> {
+        (**self).tx_owner(tx)
+    }
+
+    fn register_tx(&mut self, tx: u32, client: u16) -> Result<(), StorageErr> {
+        (**self).register_tx(tx, client)
+    }
+
+    fn unregister_tx(&mut self, tx: u32) -> Result<(), StorageErr> {
+        (**self).unregister_tx(tx)
+    }
+
+    fn flush(&mut self) -> Result<(), StorageErr> {
+        (**self).flush()
+    }
+}
+
+/// Keeps the whole state in memory. Nothing survives the process.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    accounts: HashMap<u16, ClientAccount>,
+    tx_registry: HashMap<u32, u16>,
+}
+
+impl MemoryStorage {
+    /// Constructs a new, empty [`MemoryStorage`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn account(&self, client: u16) -> Result<Option<Cow<'_, ClientAccount>>, StorageErr> {
+        Ok(self.accounts.get(&client).map(Cow::Borrowed))
+    }
+
+    fn take_account(&mut self, client: u16) -> Result<Option<ClientAccount>, StorageErr> {
+        Ok(self.accounts.remove(&client))
+    }
+
+    fn put_account(&mut self, account: ClientAccount) -> Result<(), StorageErr> {
+        self.accounts.insert(account.client(), account);
+        Ok(())
+    }
+
+    fn accounts(&self) -> Accounts<'_> {
+        Box::new(
+            self.accounts
+                .values()
+                .map(|account| Ok(Cow::Borrowed(account))),
+        )
+    }
+
+    fn tx_owner(&self, tx: u32) -> Result<Option<u16>, StorageErr> {
+        Ok(self.tx_registry.get(&tx).copied())
+    }
+
+    fn register_tx(&mut self, tx: u32, client: u16) -> Result<(), StorageErr> {
+        self.tx_registry.insert(tx, client);
+        Ok(())
+    }
+
+    fn unregister_tx(&mut self, tx: u32) -> Result<(), StorageErr> {
+        self.tx_registry.remove(&tx);
+        Ok(())
+    }
+}
+
+/// Keeps the state in a directory, so it survives between runs.
+///
+/// Every account is a JSON file under `accounts/`, replaced atomically when
+/// written. Recently updated accounts are cached and written once the cache
+/// exceeds its capacity or on [`Storage::flush`], so only a bounded number of
+/// accounts is held in memory. The owners of the transaction IDs are appended
+/// to `transactions.log` as `tx,client`, a forgotten owner as `tx,`. The format
+/// of the directory is kept in `version`; a directory without it is of version 1.
+#[derive(Debug)]
+pub struct FileStorage {
+    dir: PathBuf,
+    /// Clients with an account, on disk or in the cache.
+    clients: BTreeSet<u16>,
+    cache: HashMap<u16, ClientAccount>,
+    capacity: usize,
+    tx_registry: HashMap<u32, u16>,
+    tx_log: BufWriter<File>,
+}
+
+impl FileStorage {
+    /// Accounts cached by default before they are written to disk.
+    pub const DEFAULT_CAPACITY: usize = 1024;
+
+    /// Version of the storage format written by this engine. Version 2 records the
+    /// ledger and the entries of every account.
+    pub const VERSION: u32 = 2;
+
+    /// Open the storage in the given directory, creating it if needed.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, StorageErr> {
+        Self::with_capacity(dir, Self::DEFAULT_CAPACITY)
+    }
+
+    /// Open the storage in the given directory, caching up to `capacity` accounts.
+    pub fn with_capacity(dir: impl AsRef<Path>, capacity: usize) -> Result<Self, StorageErr> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(dir.join("accounts"))?;
+
+        let mut clients = BTreeSet::new();
+        for entry in fs::read_dir(dir.join("accounts"))? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                let client = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str()?.parse().ok())
+                    .ok_or_else(|| {
+                        StorageErr::Corrupt(format!("unexpected file {}", path.display()))
+                    })?;
+                clients.insert(client);
+            }
+        }
+
+        let version_path = dir.join("version");
+        if version_path.exists() {
+            let version = fs::read_to_string(&version_path)?;
+            if version.trim() != Self::VERSION.to_string() {
+                return Err(StorageErr::Corrupt(format!(
+                    "unsupported storage version {}",
+                    version.trim()
+                )));
+            }
+        } else if !clients.is_empty() {
+            return Err(StorageErr::Corrupt("unsupported storage version 1".into()));
+        } else {
+            fs::write(&version_path, format!("{}\n", Self::VERSION))?;
+        }
+
+        let log_path = dir.join("transactions.log");
+        let mut tx_registry = HashMap::new();
+        if log_path.exists() {
+            for (index, line) in BufReader::new(File::open(&log_path)?).lines().enumerate() {
+                let line = line?;
+                let entry = line.split_once(',').and_then(|(tx, client)| {
+                    let client = match client {
+                        "" => None,
+                        client => Some(client.parse().ok()?),
+                    };
+                    Some((tx.parse().ok()?, client))
+                });
+                let Some((tx, client)) = entry else {
+                    return Err(StorageErr::Corrupt(format!(
+                        "{}:{}: invalid entry {line:?}",
+                        log_path.display(),
+                        index + 1
+                    )));
+                };
+                match client {
+                    Some(client) => tx_registry.insert(tx, client),
+                    None => tx_registry.remove(&tx),
+                };
+            }
+        }
+        let tx_log = BufWriter::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(log_path)?,
+        );
+
+        Ok(Self {
+            dir,
+            clients,
+            cache: HashMap::new(),
+            capacity,
+            tx_registry,
+            tx_log,
+        })
+    }
+
+    fn path(&self, client: u16) -> PathBuf {
+        self.dir.join("accounts").join(format!("{client}.json"))
+    }
+
+    fn read(&self, client: u16) -> Result<Option<ClientAccount>, StorageErr> {
+        if !self.clients.contains(&client) {
+            return Ok(None);
+        }
+
+        let file = File::open(self.path(client))?;
+        Ok(Some(serde_json::from_reader(BufReader::new(file))?))
+    }
+
+    fn write(&self, account: &ClientAccount) -> Result<(), StorageErr> {
+        let path = self.path(account.client());
+        let tmp = path.with_extension("json.tmp");
+
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer(&mut writer, account)?;
+        writer.flush()?;
+        fs::rename(tmp, path)?;
+
+        Ok(())
+    }
+
+    /// Write all cached accounts to disk and empty the cache.
+    fn write_cache(&mut self) -> Result<(), StorageErr> {
+        // Accounts refer to transaction IDs, their owners reach the disk first.
+        self.tx_log.flush()?;
+        self.tx_log.get_ref().sync_data()?;
+
+        for account in self.cache.values() {
+            self.write(account)?;
+        }
+        self.cache.clear();
+
+        Ok(())
+    }
+}
+
+impl Storage for FileStorage {
+    fn account(&self, client: u16) -> Result<Option<Cow<'_, ClientAccount>>, StorageErr> {
+        match self.cache.get(&client) {
+            Some(account) => Ok(Some(Cow::Borrowed(account))),
+            None => Ok(self.read(client)?.map(Cow::Owned)),
+        }
+    }
+
+    fn take_account(&mut self, client: u16) -> Result<Option<ClientAccount>, StorageErr> {
+        match self.cache.remove(&client) {
+            Some(account) => Ok(Some(account)),
+            None => self.read(client),
+        }
+    }
+
+    fn put_account(&mut self, account: ClientAccount) -> Result<(), StorageErr> {
+        self.clients.insert(account.client());
+        self.cache.insert(account.client(), account);
+
+        if self.cache.len() > self.capacity {
+            self.write_cache()?;
+        }
+        Ok(())
+    }
+
+    fn accounts(&self) -> Accounts<'_> {
+        Box::new(
+            self.clients
+                .iter()
+                .filter_map(|&client| self.account(client).transpose()),
+        )
+    }
+
+    fn tx_owner(&self, tx: u32) -> Result<Option<u16>, StorageErr> {
+        Ok(self.tx_registry.get(&tx).copied())
+    }
+
+    fn register_tx(&mut self, tx: u32, client: u16) -> Result<(), StorageErr> {
+        writeln!(self.tx_log, "{tx},{client}")?;
+        self.tx_registry.insert(tx, client);
+        Ok(())
+    }
+
+    fn unregister_tx(&mut self, tx: u32) -> Result<(), StorageErr> {
+        writeln!(self.tx_log, "{tx},")?;
+        self.tx_registry.remove(&tx);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), StorageErr> {
+        self.write_cache()
+    }
+}
+
+impl Drop for FileStorage {
+    fn drop(&mut self) {
+        if let Err(err) = self.write_cache() {
+            log::error!("Failed to write {}: {err}", self.dir.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine::PaymentEngine, types::TransactionType};
+    use rust_decimal::Decimal;
+
+    fn deposit(client: u16, tx: u32) -> ClientAccount {
+        let mut account = ClientAccount::new(client);
+        account
+            .process_transaction(TransactionType::Deposit {
+                client,
+                tx,
+                amount: Decimal::new(15, 1),
+            })
+            .unwrap();
+        account
+    }
+
+    #[test]
+    fn test_file_storage_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut storage = FileStorage::open(dir.path()).unwrap();
+        storage.put_account(deposit(1, 10)).unwrap();
+        storage.register_tx(10, 1).unwrap();
+        storage.put_account(deposit(2, 20)).unwrap();
+        storage.register_tx(20, 2).unwrap();
+        drop(storage);
+
+        let mut storage = FileStorage::open(dir.path()).unwrap();
+        assert_eq!(storage.tx_owner(10).unwrap(), Some(1));
+        assert_eq!(storage.tx_owner(20).unwrap(), Some(2));
+        assert_eq!(storage.tx_owner(30).unwrap(), None);
+        storage.register_tx(30, 2).unwrap();
+        storage.unregister_tx(30).unwrap();
+        drop(storage);
+
+        let mut storage = FileStorage::open(dir.path()).unwrap();
+        assert_eq!(storage.tx_owner(30).unwrap(), None);
+
+        let account = storage.take_account(1).unwrap().unwrap();
+        assert_eq!(account.available(), Decimal::new(15, 1));
+        assert!(account.dispute_state(10).is_some());
+        storage.put_account(account).unwrap();
+
+        let clients: Vec<_> = storage
+            .accounts()
+            .map(|account| account.unwrap().client())
+            .collect();
+        assert_eq!(clients, [1, 2]);
+        assert!(storage.account(3).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_file_storage_capacity() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut storage = FileStorage::with_capacity(dir.path(), 1).unwrap();
+        storage.put_account(deposit(1, 10)).unwrap();
+        assert!(!dir.path().join("accounts/1.json").exists());
+
+        // Exceeding the capacity writes the cached accounts.
+        storage.put_account(deposit(2, 20)).unwrap();
+        assert!(dir.path().join("accounts/1.json").exists());
+        assert!(dir.path().join("accounts/2.json").exists());
+        assert_eq!(
+            storage.account(1).unwrap().unwrap().total(),
+            Decimal::new(15, 1)
+        );
+    }
+
+    #[test]
+    fn test_file_storage_owners_before_accounts() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FileStorage::with_capacity(dir.path(), 1).unwrap();
+        let mut engine = PaymentEngine::with_storage(storage, Default::default());
+
+        for (client, tx) in [(1, 10), (2, 20)] {
+            engine
+                .process_transaction(TransactionType::Deposit {
+                    client,
+                    tx,
+                    amount: Decimal::ONE,
+                })
+                .unwrap();
+        }
+
+        // The accounts are written once the capacity is exceeded, the owners of
+        // their transactions are on disk by then.
+        assert!(dir.path().join("accounts/2.json").exists());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("transactions.log")).unwrap(),
+            "10,1\n20,2\n"
+        );
+    }
+
+    #[test]
+    fn test_file_storage_corrupt_log() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("transactions.log"), "1,1\nfoo\n").unwrap();
+
+        let err = FileStorage::open(dir.path()).unwrap_err();
+        assert_eq!(err.code(), "storage_corrupt");
+    }
+
+    #[test]
+    fn test_file_storage_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = FileStorage::open(dir.path()).unwrap();
+        storage.put_account(deposit(1, 10)).unwrap();
+        drop(storage);
+        FileStorage::open(dir.path()).unwrap();
+
+        // Accounts written before the version was kept have no ledger.
+        fs::remove_file(dir.path().join("version")).unwrap();
+        let err = FileStorage::open(dir.path()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "corrupt storage: unsupported storage version 1"
+        );
+
+        fs::write(dir.path().join("version"), "3\n").unwrap();
+        let err = FileStorage::open(dir.path()).unwrap_err();
+        assert_eq!(err.code(), "storage_corrupt");
+    }
+}
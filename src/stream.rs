@@ -0,0 +1,227 @@
+use crate::{
+    client::BalanceDelta,
+    engine::PaymentEngine,
+    error::EngineErr,
+    input::{self, InputErr, InputFormat, InputRow, Rows},
+    storage::Storage,
+    types::TransactionType,
+};
+
+use std::{fmt, io::Read};
+
+/// The reason a row was not applied.
+#[derive(Debug)]
+pub enum RowErr {
+    /// The row does not describe a valid transaction.
+    Input(InputErr),
+    /// The engine rejected the transaction.
+    Rejected(EngineErr),
+}
+
+impl RowErr {
+    /// A stable, machine-readable code of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Input(err) => err.code(),
+            Self::Rejected(err) => err.code(),
+        }
+    }
+}
+
+impl fmt::Display for RowErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Input(err) => write!(f, "{err}"),
+            Self::Rejected(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for RowErr {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Input(err) => Some(err),
+            Self::Rejected(err) => Some(err),
+        }
+    }
+}
+
+/// The outcome of a row applied to the engine.
+#[derive(Debug)]
+pub struct RowOutcome {
+    /// Line number of the row in the input, or position of the transaction
+    /// starting at 1.
+    pub line: u64,
+    /// Raw content of the row, empty for transactions not read from an input.
+    pub raw: String,
+    /// The transaction described by the row, if it is valid.
+    pub transaction: Option<TransactionType>,
+    /// The change of the balances of the account, or the reason the row was not
+    /// applied. A rejection with [`EngineErrKind::Storage`](crate::error::EngineErrKind::Storage)
+    /// means the state of the engine could not be loaded or stored.
+    pub result: Result<BalanceDelta, RowErr>,
+}
+
+/// Applies rows to a [`PaymentEngine`] as they are pulled, yielding the outcome
+/// of every row.
+pub struct Outcomes<'a, S, I> {
+    engine: &'a mut PaymentEngine<S>,
+    rows: I,
+}
+
+impl<S, I> Outcomes<'_, S, I> {
+    /// The engine, with all rows yielded so far applied.
+    pub fn engine(&self) -> &PaymentEngine<S> {
+        self.engine
+    }
+}
+
+impl<S: Storage, I: Iterator<Item = InputRow>> Iterator for Outcomes<'_, S, I> {
+    type Item = RowOutcome;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let row = self.rows.next()?;
+        Some(self.engine.process_row(row))
+    }
+}
+
+/// The rows of transactions not read from an input, numbered from 1.
+pub struct Transactions<I> {
+    txs: I,
+    line: u64,
+}
+
+impl<I: Iterator<Item = TransactionType>> Iterator for Transactions<I> {
+    type Item = InputRow;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let tx = self.txs.next()?;
+        self.line += 1;
+        Some(InputRow {
+            line: self.line,
+            raw: String::new(),
+            transaction: Ok(tx),
+        })
+    }
+}
+
+impl<S: Storage> PaymentEngine<S> {
+    /// Apply a row of the input.
+    pub fn process_row(&mut self, row: InputRow) -> RowOutcome {
+        let (transaction, result) = match row.transaction {
+            Ok(tx) => {
+                let result = self
+                    .process_transaction(tx.clone())
+                    .map_err(RowErr::Rejected);
+                (Some(tx), result)
+            }
+            Err(err) => (None, Err(RowErr::Input(err))),
+        };
+
+        RowOutcome {
+            line: row.line,
+            raw: row.raw,
+            transaction,
+            result,
+        }
+    }
+
+    /// Apply the rows one at a time as the returned iterator is advanced.
+    pub fn process_rows<I>(&mut self, rows: I) -> Outcomes<'_, S, I::IntoIter>
+    where
+        I: IntoIterator<Item = InputRow>,
+    {
+        Outcomes {
+            engine: self,
+            rows: rows.into_iter(),
+        }
+    }
+
+    /// Read the rows of the input in the given format and apply them one at a time
+    /// as the returned iterator is advanced.
+    pub fn process_reader<'a, R: Read + 'a>(
+        &'a mut self,
+        reader: R,
+        format: InputFormat,
+    ) -> Result<Outcomes<'a, S, Rows<'a>>, Box<dyn std::error::Error>> {
+        let rows = input::read_rows(reader, format)?;
+        Ok(self.process_rows(rows))
+    }
+
+    /// Apply the transactions one at a time as the returned iterator is advanced.
+    pub fn process_iter<I>(&mut self, txs: I) -> Outcomes<'_, S, Transactions<I::IntoIter>>
+    where
+        I: IntoIterator<Item = TransactionType>,
+    {
+        self.process_rows(Transactions {
+            txs: txs.into_iter(),
+            line: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client::ClientErr, error::EngineErrKind};
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn test_process_reader() {
+        let input = "type,client,tx,amount\ndeposit,1,1,2.0\nwithdrawal,1,2,5.0\nrefund,1,3,1.0\n";
+        let mut engine = PaymentEngine::new();
+
+        let outcomes: Vec<_> = engine
+            .process_reader(input.as_bytes(), InputFormat::Csv)
+            .unwrap()
+            .collect();
+        assert_eq!(outcomes.len(), 3);
+
+        assert_eq!(outcomes[0].line, 2);
+        assert_eq!(
+            outcomes[0].result.as_ref().unwrap().available,
+            Decimal::new(20, 1)
+        );
+
+        assert_eq!(outcomes[1].raw, "withdrawal,1,2,5.0");
+        assert!(outcomes[1].transaction.is_some());
+        let Err(RowErr::Rejected(err)) = &outcomes[1].result else {
+            panic!("expected a rejection");
+        };
+        assert!(matches!(
+            err.kind(),
+            EngineErrKind::Client(ClientErr::InsufficientFunds)
+        ));
+
+        assert!(outcomes[2].transaction.is_none());
+        assert!(matches!(outcomes[2].result, Err(RowErr::Input(_))));
+
+        let account = engine.account(1).unwrap().unwrap();
+        assert_eq!(account.total(), Decimal::new(20, 1));
+    }
+
+    #[test]
+    fn test_process_iter() {
+        let txs = (1..=3).map(|tx| TransactionType::Deposit {
+            client: 1,
+            tx,
+            amount: Decimal::ONE,
+        });
+        let mut engine = PaymentEngine::new();
+
+        // Rows are applied lazily, as they are pulled.
+        let mut outcomes = engine.process_iter(txs);
+        let first = outcomes.next().unwrap();
+        assert_eq!(first.line, 1);
+        assert!(first.result.is_ok());
+        let account = outcomes.engine().account(1).unwrap().unwrap();
+        assert_eq!(account.total(), Decimal::ONE);
+
+        assert_eq!(
+            outcomes.map(|outcome| outcome.line).collect::<Vec<_>>(),
+            [2, 3]
+        );
+        let account = engine.account(1).unwrap().unwrap();
+        assert_eq!(account.total(), Decimal::from(3));
+    }
+}
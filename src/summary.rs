@@ -0,0 +1,151 @@
+use crate::{
+    engine::PaymentEngine,
+    storage::{Storage, StorageErr},
+};
+
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt};
+
+/// Counters describing the outcome of a run.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct RunSummary {
+    /// Rows read from the inputs.
+    pub rows_read: u64,
+    /// Rows parsed into a transaction.
+    pub rows_parsed: u64,
+    /// Transactions applied by the engine.
+    pub rows_applied: u64,
+    /// Rows rejected while parsing or by the engine.
+    pub rows_rejected: u64,
+    /// Rejected rows by reason code.
+    pub rejected_by_reason: BTreeMap<String, u64>,
+    /// Accounts touched by the run.
+    pub accounts_touched: u64,
+    /// Accounts locked at the end of the run.
+    pub accounts_locked: u64,
+}
+
+impl RunSummary {
+    /// Constructs a new, empty [`RunSummary`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a row that was parsed but not handed to the engine.
+    pub fn parsed(&mut self) {
+        self.rows_read += 1;
+        self.rows_parsed += 1;
+    }
+
+    /// Count a row that was parsed and applied.
+    pub fn applied(&mut self) {
+        self.rows_read += 1;
+        self.rows_parsed += 1;
+        self.rows_applied += 1;
+    }
+
+    /// Count a row that could not be parsed.
+    pub fn malformed(&mut self, code: &str) {
+        self.rows_read += 1;
+        self.reject(code);
+    }
+
+    /// Count a row that was parsed but rejected by the engine.
+    pub fn rejected(&mut self, code: &str) {
+        self.rows_read += 1;
+        self.rows_parsed += 1;
+        self.reject(code);
+    }
+
+    fn reject(&mut self, code: &str) {
+        self.rows_rejected += 1;
+        *self.rejected_by_reason.entry(code.to_string()).or_default() += 1;
+    }
+
+    /// Count the accounts of the engine at the end of the run.
+    pub fn count_accounts<S: Storage>(
+        &mut self,
+        engine: &PaymentEngine<S>,
+    ) -> Result<(), StorageErr> {
+        self.accounts_touched = 0;
+        self.accounts_locked = 0;
+        for account in engine.accounts() {
+            self.accounts_touched += 1;
+            self.accounts_locked += account?.is_locked() as u64;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "rows: {} read, {} parsed, {} applied, {} rejected",
+            self.rows_read, self.rows_parsed, self.rows_applied, self.rows_rejected
+        )?;
+        for (code, count) in &self.rejected_by_reason {
+            writeln!(f, "  {code}: {count}")?;
+        }
+        write!(
+            f,
+            "accounts: {} touched, {} locked",
+            self.accounts_touched, self.accounts_locked
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::TransactionType;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn test_summary() {
+        let mut engine = PaymentEngine::new();
+        let mut summary = RunSummary::new();
+
+        for tx in [
+            TransactionType::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Decimal::new(10, 1),
+            },
+            TransactionType::Withdrawal {
+                client: 2,
+                tx: 2,
+                amount: Decimal::new(10, 1),
+            },
+            TransactionType::Dispute { client: 1, tx: 1 },
+            TransactionType::Chargeback { client: 1, tx: 1 },
+            TransactionType::Deposit {
+                client: 1,
+                tx: 3,
+                amount: Decimal::new(10, 1),
+            },
+        ] {
+            match engine.process_transaction(tx) {
+                Ok(_) => summary.applied(),
+                Err(err) => summary.rejected(err.code()),
+            }
+        }
+        summary.malformed("malformed");
+        summary.count_accounts(&engine).unwrap();
+
+        assert_eq!(summary.rows_read, 6);
+        assert_eq!(summary.rows_parsed, 5);
+        assert_eq!(summary.rows_applied, 3);
+        assert_eq!(summary.rows_rejected, 3);
+        assert_eq!(
+            summary.rejected_by_reason,
+            BTreeMap::from([
+                ("account_locked".to_string(), 1),
+                ("insufficient_funds".to_string(), 1),
+                ("malformed".to_string(), 1),
+            ])
+        );
+        assert_eq!(summary.accounts_touched, 2);
+        assert_eq!(summary.accounts_locked, 1);
+    }
+}
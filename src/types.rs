@@ -1,11 +1,14 @@
 use std::str::FromStr;
 
+use crate::error::{EngineErr, ParseErr};
+
 use rust_decimal::Decimal;
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_json::value::RawValue;
 
 pub const PRECISION: u32 = 4;
 
-/// Represents a transaction in the CSV file.
+/// Represents a transaction row of the input, as found in the CSV file.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CsvTransaction {
     #[serde(rename = "type")]
@@ -15,8 +18,60 @@ pub struct CsvTransaction {
     amount: Option<String>,
 }
 
+impl From<TransactionType> for CsvTransaction {
+    fn from(tx: TransactionType) -> Self {
+        Self {
+            ty: tx.type_name().to_string(),
+            client: tx.client_id(),
+            tx: tx.transaction_id(),
+            amount: tx.amount().map(|amount| amount.to_string()),
+        }
+    }
+}
+
+/// Represents a JSON row of the input in the flat CSV shape.
+#[derive(Debug, Deserialize)]
+pub(crate) struct JsonTransaction {
+    #[serde(rename = "type")]
+    ty: String,
+    client: u16,
+    tx: u32,
+    #[serde(default, deserialize_with = "json_amount")]
+    amount: Option<String>,
+}
+
+impl From<JsonTransaction> for CsvTransaction {
+    fn from(tx: JsonTransaction) -> Self {
+        Self {
+            ty: tx.ty,
+            client: tx.client,
+            tx: tx.tx,
+            amount: tx.amount,
+        }
+    }
+}
+
+/// Deserialize an amount given as a JSON string or number. A number is kept as
+/// written, so no digits are lost to floating point.
+fn json_amount<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    let Some(raw) = Option::<Box<RawValue>>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    let raw = raw.get();
+    match raw.chars().next() {
+        Some('"') => serde_json::from_str(raw)
+            .map(Some)
+            .map_err(de::Error::custom),
+        Some('-' | '0'..='9') => Ok(Some(raw.to_string())),
+        _ => Err(de::Error::invalid_type(
+            de::Unexpected::Other(raw),
+            &"a string or a number",
+        )),
+    }
+}
+
 /// Represents the type of transaction.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TransactionType {
     /// A deposit transaction.
     Deposit {
@@ -60,17 +115,39 @@ impl TransactionType {
             Self::Chargeback { tx, .. } => *tx,
         }
     }
+
+    /// Returns the amount of the transaction, if it carries one.
+    pub fn amount(&self) -> Option<Decimal> {
+        match self {
+            Self::Deposit { amount, .. } | Self::Withdrawal { amount, .. } => Some(*amount),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the transaction type as used in the CSV input.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Deposit { .. } => "deposit",
+            Self::Withdrawal { .. } => "withdrawal",
+            Self::Dispute { .. } => "dispute",
+            Self::Resolve { .. } => "resolve",
+            Self::Chargeback { .. } => "chargeback",
+        }
+    }
 }
 
 impl TryFrom<CsvTransaction> for TransactionType {
-    type Error = &'static str;
+    type Error = EngineErr;
 
     fn try_from(value: CsvTransaction) -> Result<Self, Self::Error> {
+        let err = |kind: ParseErr| EngineErr::new(value.client, value.tx, kind);
+
         // Small helper to ensure we always have the required precision.
-        let parse_decimal = |value: String| -> Result<Decimal, Self::Error> {
-            let dec = Decimal::from_str(&value).map_err(|_| "invalid decimal")?;
+        let parse_decimal = |value: Option<String>| -> Result<Decimal, Self::Error> {
+            let value = value.ok_or_else(|| err(ParseErr::MissingAmount))?;
+            let dec = Decimal::from_str(&value).map_err(|_| err(ParseErr::InvalidDecimal))?;
             if dec.scale() > PRECISION {
-                return Err("Invalid precision");
+                return Err(err(ParseErr::InvalidPrecision));
             }
             Ok(dec)
         };
@@ -79,12 +156,12 @@ impl TryFrom<CsvTransaction> for TransactionType {
             "deposit" => Ok(Self::Deposit {
                 client: value.client,
                 tx: value.tx,
-                amount: parse_decimal(value.amount.ok_or("No amount provided")?)?,
+                amount: parse_decimal(value.amount)?,
             }),
             "withdrawal" => Ok(Self::Withdrawal {
                 client: value.client,
                 tx: value.tx,
-                amount: parse_decimal(value.amount.ok_or("No amount provided")?)?,
+                amount: parse_decimal(value.amount)?,
             }),
             "dispute" => Ok(Self::Dispute {
                 client: value.client,
@@ -98,7 +175,43 @@ impl TryFrom<CsvTransaction> for TransactionType {
                 client: value.client,
                 tx: value.tx,
             }),
-            _ => Err("Unknown transaction type"),
+            _ => Err(err(ParseErr::UnknownType)),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::EngineErrKind;
+
+    fn csv_tx(ty: &str, amount: Option<&str>) -> CsvTransaction {
+        CsvTransaction {
+            ty: ty.to_string(),
+            client: 3,
+            tx: 9,
+            amount: amount.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (record, expected) in [
+            (csv_tx("deposi", Some("1.0")), ParseErr::UnknownType),
+            (csv_tx("deposit", None), ParseErr::MissingAmount),
+            (csv_tx("withdrawal", Some("abc")), ParseErr::InvalidDecimal),
+            (
+                csv_tx("deposit", Some("1.00001")),
+                ParseErr::InvalidPrecision,
+            ),
+        ] {
+            let err = TransactionType::try_from(record).unwrap_err();
+            assert_eq!(err.client(), 3);
+            assert_eq!(err.tx(), 9);
+            assert!(matches!(err.kind(), EngineErrKind::Parse(kind) if *kind == expected));
+        }
+
+        let tx = TransactionType::try_from(csv_tx("deposit", Some("1.0001"))).unwrap();
+        assert_eq!(tx.amount(), Some(Decimal::new(10001, 4)));
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+const INPUT: &str = "type,client,tx,amount
+deposit,1,1,5.0
+withdrawal,1,2,9.0
+deposit,2,3,1.0
+";
+
+fn tx(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_tx"))
+        .args(args)
+        .output()
+        .expect("the binary runs")
+}
+
+fn write_input(dir: &Path, name: &str, content: &str) -> PathBuf {
+    let path = dir.join(name);
+    fs::write(&path, content).unwrap();
+    path
+}
+
+fn path(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+#[test]
+fn test_exit_codes() {
+    let dir = tempfile::tempdir().unwrap();
+    let clean = write_input(
+        dir.path(),
+        "clean.csv",
+        "type,client,tx,amount\ndeposit,1,1,1.0\n",
+    );
+    let rejected = write_input(dir.path(), "rejected.csv", INPUT);
+
+    let output = tx(&["process", path(&clean)]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n"
+    );
+
+    assert_eq!(tx(&[path(&rejected)]).status.code(), Some(1));
+    assert_eq!(tx(&["process", "--unknown-flag"]).status.code(), Some(2));
+    let missing = dir.path().join("missing.csv");
+    assert_eq!(tx(&["process", path(&missing)]).status.code(), Some(4));
+    let output = tx(&["statement", "7", path(&clean)]);
+    assert_eq!(output.status.code(), Some(8));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("unknown client 7"), "{stderr}");
+    assert_eq!(tx(&["inspect", "7", path(&clean)]).status.code(), Some(8));
+
+    assert_eq!(
+        tx(&["inspect", "1", path(&clean), "--format", "csv"])
+            .status
+            .code(),
+        Some(2)
+    );
+}
+
+#[test]
+fn test_strict() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(), "input.csv", INPUT);
+    let accounts = dir.path().join("accounts.csv");
+    let rejections = dir.path().join("rejections.csv");
+
+    let output = tx(&[
+        "process",
+        "--strict",
+        path(&input),
+        "--output",
+        path(&accounts),
+        "--rejections",
+        path(&rejections),
+    ]);
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(":3 (strict mode)"), "{stderr}");
+
+    // No account output is written, the rejection report is.
+    assert!(!accounts.exists());
+    let report = fs::read_to_string(&rejections).unwrap();
+    assert_eq!(report.lines().count(), 2, "{report}");
+    assert!(report.contains("insufficient_funds"), "{report}");
+
+    // The summary covers the rows up to the aborting one.
+    assert!(stderr.contains("rows: 2 read"), "{stderr}");
+
+    // Without an output path nothing is written to stdout.
+    let output = tx(&["process", "--strict", path(&input)]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(output.stdout.is_empty());
+
+    // A summary written as JSON covers the aborted run as well.
+    let summary = dir.path().join("summary.json");
+    let output = tx(&[
+        "validate",
+        "--strict",
+        path(&input),
+        "--summary",
+        path(&summary),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    let output = tx(&[
+        "process",
+        "--strict",
+        path(&input),
+        "--summary",
+        path(&summary),
+    ]);
+    assert_eq!(output.status.code(), Some(3));
+    let summary = fs::read_to_string(&summary).unwrap();
+    assert!(summary.contains("\"rows_rejected\": 1"), "{summary}");
+
+    // The rows after the aborting one are not processed.
+    let output = tx(&["replay", "--strict", path(&input)]);
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains(",3,"), "{stdout}");
+}
+
+#[test]
+fn test_audit() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(), "input.csv", INPUT);
+    let snapshot = dir.path().join("snapshot.json");
+
+    let output = tx(&["process", path(&input), "--save-snapshot", path(&snapshot)]);
+    assert_eq!(output.status.code(), Some(1));
+    let output = tx(&["audit", path(&snapshot)]);
+    assert_eq!(output.status.code(), Some(0));
+
+    // A snapshot that cannot be restored is audited.
+    let mut stored: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&snapshot).unwrap()).unwrap();
+    stored["accounts"][0]["dispute_state"] = serde_json::json!({});
+    fs::write(&snapshot, stored.to_string()).unwrap();
+
+    let output = tx(&["audit", path(&snapshot)]);
+    assert_eq!(output.status.code(), Some(7));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("missing_dispute_state: [client 1] transaction 1 has no dispute state"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn test_journal_conflicts() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(), "input.csv", INPUT);
+    let journal = dir.path().join("journal.jsonl");
+    let snapshot = dir.path().join("snapshot.json");
+
+    // The rows of the journal would be applied again on top of the snapshot.
+    let output = tx(&[
+        "process",
+        path(&input),
+        "--journal",
+        path(&journal),
+        "--load-snapshot",
+        path(&snapshot),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("cannot be used with"), "{stderr}");
+
+    let output = tx(&[
+        "serve",
+        "127.0.0.1:0",
+        "--journal",
+        path(&journal),
+        "--load-snapshot",
+        path(&snapshot),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(!journal.exists());
+}